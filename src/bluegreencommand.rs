@@ -0,0 +1,185 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use serde_json::json;
+
+use crate::command::Command;
+use crate::duration;
+use crate::foilerror::FoilError;
+use crate::gitcontext::GitContext;
+use crate::helmclient::HelmClient;
+use crate::helmruntime::HelmRuntime;
+use crate::k8sname::{self, RELEASE_NAME_MAX};
+use crate::statefile;
+use crate::templatevalue::Value;
+use crate::upgradecommand::Upgrade;
+use clap::ArgMatches;
+
+/**
+Blue/green by branch: the branch being deployed becomes a colour of its own, released as
+`RELEASE-<branch>`, next to the colour that is live. Once it is up the router release is
+upgraded to send traffic to it, with `.Branch.Name` the new colour and `.Previous.Branch` the
+one it replaces. The live colour is kept in a state file, which is where the next run gets
+`.Previous.Branch` from.
+**/
+pub(crate) struct BlueGreenCommand<'a> {
+    helm_runtime: &'a mut HelmRuntime,
+}
+
+impl<'a> BlueGreenCommand<'a> {
+    pub(crate) fn new(execute_helm_command: &'a mut HelmRuntime) -> BlueGreenCommand<'a> {
+        BlueGreenCommand {
+            helm_runtime: execute_helm_command,
+        }
+    }
+
+    /*
+    render the chart's values with the colour variables and `helm upgrade --install` it
+    */
+    fn deploy(
+        &mut self,
+        matches: &ArgMatches,
+        bluegreen_command: &ArgMatches,
+        helm_binary: &str,
+        upgrade: &Upgrade,
+    ) -> Result<(), FoilError> {
+        let helm_runtime = self.get_helm_runtime();
+        helm_runtime.reset();
+        let mut helm_command =
+            upgrade.prepare(helm_runtime, matches, bluegreen_command, helm_binary)?;
+        helm_runtime.execute_helm(&mut helm_command)
+    }
+}
+
+impl<'a> Command for BlueGreenCommand<'a> {
+    fn get_helm_runtime(&mut self) -> &mut HelmRuntime {
+        self.helm_runtime
+    }
+
+    fn execute(
+        &mut self,
+        matches: &ArgMatches,
+        commandline: &Option<&str>,
+        helm_binary: String,
+    ) -> Result<(), FoilError> {
+        if let Some(command) = commandline {
+            if let Some(bluegreen_command) = matches.subcommand_matches(command) {
+                let release = self
+                    .get_helm_runtime()
+                    .release_name(bluegreen_command.value_of("RELEASE"))?
+                    .unwrap_or_default();
+                let chart = bluegreen_command.value_of("CHART").unwrap_or_default();
+                let router_chart = bluegreen_command
+                    .value_of("router-chart")
+                    .unwrap_or_default();
+                let router_release = bluegreen_command
+                    .value_of("router-release")
+                    .unwrap_or(&release)
+                    .to_string();
+                let grace_period =
+                    duration::parse(bluegreen_command.value_of("grace-period").unwrap_or("0"))
+                        .map_err(|e| FoilError::Config(format!("--grace-period: {}", e)))?;
+                let state_path = bluegreen_command
+                    .value_of("state-file")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| statefile::default_path("bluegreen", &release));
+
+                let branch = GitContext::discover(Path::new(chart))
+                    .branch
+                    .ok_or_else(|| {
+                        FoilError::Config(
+                            "bluegreen needs the branch being deployed, none found in git or CI"
+                                .to_string(),
+                        )
+                    })?;
+                let colour_release =
+                    k8sname::dns1123(&format!("{}-{}", release, branch), RELEASE_NAME_MAX);
+
+                // deploying the live branch again keeps the colour it replaced
+                let state = statefile::load(&state_path)?.unwrap_or_default();
+                let (previous_branch, previous_release) =
+                    if state["live_branch"].as_str() == Some(branch.as_str()) {
+                        (&state["previous_branch"], &state["previous_release"])
+                    } else {
+                        (&state["live_branch"], &state["live_release"])
+                    };
+                let previous_branch = previous_branch.as_str().map(|b| b.to_string());
+                let previous_release = previous_release.as_str().map(|r| r.to_string());
+
+                // the previous colour only comes from the state file, the branch git last had
+                // checked out was never deployed as one
+                let colours = [
+                    ("Branch.Name", Some(Value::from(branch.as_str()))),
+                    ("Live.Release", Some(Value::from(colour_release.as_str()))),
+                    (
+                        "Previous.Branch",
+                        previous_branch.as_deref().map(Value::from),
+                    ),
+                    (
+                        "Previous.Release",
+                        previous_release.as_deref().map(Value::from),
+                    ),
+                ];
+
+                eprintln!("deploying {} as {}", branch, colour_release);
+                // the colours win over what git says about the branches; one that isn't known
+                // is unset rather than left as git's guess
+                let colour = Upgrade {
+                    install: true,
+                    vars: &colours,
+                    ..Upgrade::new(Some(&colour_release), Some(chart))
+                };
+                self.deploy(matches, bluegreen_command, &helm_binary, &colour)?;
+
+                eprintln!("switching {} to {}", router_release, colour_release);
+                let router = Upgrade {
+                    value_files_arg: "router-values",
+                    set_arg: "router-set",
+                    install: true,
+                    vars: &colours,
+                    ..Upgrade::new(Some(&router_release), Some(router_chart))
+                };
+                self.deploy(matches, bluegreen_command, &helm_binary, &router)
+                    .inspect_err(|_| {
+                        eprintln!(
+                            "{} was not switched, {} is still live",
+                            router_release,
+                            previous_release.as_deref().unwrap_or("the previous colour")
+                        )
+                    })?;
+
+                let mut live = json!({
+                    "release": release,
+                    "router_release": router_release,
+                    "live_branch": branch,
+                    "live_release": colour_release,
+                    "previous_branch": previous_branch,
+                    "previous_release": previous_release,
+                });
+                statefile::save(&state_path, &live)?;
+
+                let old_release = previous_release.filter(|old| *old != colour_release);
+                if let (true, Some(old_release)) =
+                    (bluegreen_command.is_present("delete-old"), old_release)
+                {
+                    if !grace_period.is_zero() {
+                        eprintln!(
+                            "waiting {} before deleting {}",
+                            bluegreen_command
+                                .value_of("grace-period")
+                                .unwrap_or_default(),
+                            old_release
+                        );
+                        thread::sleep(grace_period);
+                    }
+                    let client = HelmClient::new(&helm_binary, self.get_helm_runtime(), matches);
+                    client.delete(&old_release)?;
+                    live["previous_release"] = serde_json::Value::Null;
+                    statefile::save(&state_path, &live)?;
+                    eprintln!("deleted {}", old_release);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use serde_json::json;
+
+use crate::command::Command;
+use crate::deploycheck;
+use crate::duration;
+use crate::foilerror::FoilError;
+use crate::helmclient::HelmClient;
+use crate::helmruntime::HelmRuntime;
+use crate::statefile;
+use crate::templatevalue::Value;
+use crate::upgradecommand;
+use clap::ArgMatches;
+
+const IN_PROGRESS: &str = "in-progress";
+const COMPLETE: &str = "complete";
+const ROLLED_BACK: &str = "rolled-back";
+
+/**
+A progressive rollout: one `helm upgrade` per step of `--steps`, each with the values rendered
+again for that step's `.Canary.Percentage`, and `.Starting.Canary.Percentage` the first step's.
+Between steps foil waits `--interval` and runs the `--check` command. When a step fails the
+release is rolled back to the revision of the last good step, or to the one from before the
+rollout. Progress is kept in a state file so `--resume` can carry on after an interruption.
+**/
+pub(crate) struct CanaryCommand<'a> {
+    helm_runtime: &'a mut HelmRuntime,
+}
+
+impl<'a> CanaryCommand<'a> {
+    pub(crate) fn new(execute_helm_command: &'a mut HelmRuntime) -> CanaryCommand<'a> {
+        CanaryCommand {
+            helm_runtime: execute_helm_command,
+        }
+    }
+
+    /*
+    render the values again for this step and upgrade the release
+    */
+    fn run_step(
+        &mut self,
+        matches: &ArgMatches,
+        canary_command: &ArgMatches,
+        helm_binary: &str,
+        starting: u32,
+        percentage: u32,
+    ) -> Result<(), FoilError> {
+        let helm_runtime = self.get_helm_runtime();
+        helm_runtime.reset();
+        helm_runtime.set_var("Canary.Percentage", Value::Int(i64::from(percentage)));
+        // every step, resumed ones too, so the values render the same whichever step it is
+        helm_runtime.set_var(
+            "Starting.Canary.Percentage",
+            Value::Int(i64::from(starting)),
+        );
+        let mut helm_command =
+            upgradecommand::prepare_upgrade(helm_runtime, matches, canary_command, helm_binary)?;
+        helm_runtime.execute_helm(&mut helm_command)
+    }
+}
+
+impl<'a> Command for CanaryCommand<'a> {
+    fn get_helm_runtime(&mut self) -> &mut HelmRuntime {
+        self.helm_runtime
+    }
+
+    fn execute(
+        &mut self,
+        matches: &ArgMatches,
+        commandline: &Option<&str>,
+        helm_binary: String,
+    ) -> Result<(), FoilError> {
+        if let Some(command) = commandline {
+            if let Some(canary_command) = matches.subcommand_matches(command) {
+                let steps = parse_steps(canary_command)?;
+                let interval = duration::parse(canary_command.value_of("interval").unwrap_or("0"))
+                    .map_err(|e| FoilError::Config(format!("--interval: {}", e)))?;
+                let release = self
+                    .get_helm_runtime()
+                    .release_name(canary_command.value_of("RELEASE"))?
+                    .unwrap_or_default();
+                let state_path = canary_command
+                    .value_of("state-file")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| statefile::default_path("canary", &release));
+                let client = HelmClient::new(&helm_binary, self.get_helm_runtime(), matches);
+
+                let mut rollout = Rollout::start(
+                    &state_path,
+                    &release,
+                    &steps,
+                    canary_command.is_present("resume"),
+                    &client,
+                )?;
+
+                let first_step = rollout.completed.len();
+                for (index, percentage) in steps.iter().enumerate().skip(first_step) {
+                    eprintln!(
+                        "canary step {}/{}: {} at {}%",
+                        index + 1,
+                        steps.len(),
+                        release,
+                        percentage
+                    );
+                    let result =
+                        self.run_step(matches, canary_command, &helm_binary, steps[0], *percentage);
+                    let result = result.and_then(|_| {
+                        if index + 1 < steps.len() && !interval.is_zero() {
+                            eprintln!(
+                                "waiting {} before checking",
+                                canary_command.value_of("interval").unwrap_or_default()
+                            );
+                            thread::sleep(interval);
+                        }
+                        match canary_command.value_of("check") {
+                            Some(check) => deploycheck::run_check(
+                                check,
+                                &[
+                                    ("HELM_FOIL_RELEASE_NAME", release.clone()),
+                                    ("HELM_FOIL_CANARY_PERCENTAGE", percentage.to_string()),
+                                ],
+                            ),
+                            None => Ok(()),
+                        }
+                    });
+
+                    if let Err(error) = result {
+                        eprintln!(
+                            "canary step {}% of {} failed: {}",
+                            percentage, release, error
+                        );
+                        rollout.roll_back(&client)?;
+                        return Err(error);
+                    }
+                    let revision = client.current_revision(&release)?.map(|r| r.revision);
+                    rollout.step_done(*percentage, revision)?;
+                }
+
+                rollout.finish()?;
+                eprintln!("canary rollout of {} complete", release);
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+--steps 5,25,50,100: percentages between 1 and 100, each larger than the last
+*/
+fn parse_steps(canary_command: &ArgMatches) -> Result<Vec<u32>, FoilError> {
+    let mut steps: Vec<u32> = Vec::new();
+    for step in canary_command.values_of("steps").into_iter().flatten() {
+        let percentage = step
+            .trim()
+            .trim_end_matches('%')
+            .parse::<u32>()
+            .ok()
+            .filter(|percentage| (1..=100).contains(percentage))
+            .ok_or_else(|| {
+                FoilError::Config(format!(
+                    "--steps {} is not a percentage from 1 to 100",
+                    step
+                ))
+            })?;
+        if steps.last().is_some_and(|last| *last >= percentage) {
+            return Err(FoilError::Config(
+                "--steps must go up, e.g. 5,25,50,100".to_string(),
+            ));
+        }
+        steps.push(percentage);
+    }
+    if steps.is_empty() {
+        return Err(FoilError::Config(
+            "--steps needs at least one percentage".to_string(),
+        ));
+    }
+    Ok(steps)
+}
+
+/**
+How far a rollout got, mirrored to the state file after every change.
+**/
+struct Rollout {
+    state_path: PathBuf,
+    release: String,
+    steps: Vec<u32>,
+    // the last revision that worked before the rollout, none when there was none
+    start_revision: Option<u64>,
+    // (percentage, revision) of every step that passed
+    completed: Vec<(u32, Option<u64>)>,
+}
+
+impl Rollout {
+    /*
+    a new rollout, or with --resume the interrupted one from the state file
+    */
+    fn start(
+        state_path: &Path,
+        release: &str,
+        steps: &[u32],
+        resume: bool,
+        client: &HelmClient,
+    ) -> Result<Rollout, FoilError> {
+        let state = statefile::load(state_path)?;
+        let in_progress = state
+            .as_ref()
+            .filter(|state| state["status"] == IN_PROGRESS);
+
+        match (in_progress, resume) {
+            (Some(state), true) => {
+                let saved_steps: Vec<u32> = state["steps"]
+                    .as_array()
+                    .map(|steps| {
+                        steps
+                            .iter()
+                            .filter_map(|step| step.as_u64().map(|step| step as u32))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                if saved_steps != steps {
+                    return Err(FoilError::Config(format!(
+                        "the rollout in {} has steps {:?}, resume it with the same --steps",
+                        state_path.display(),
+                        saved_steps
+                    )));
+                }
+                let completed = state["completed"]
+                    .as_array()
+                    .map(|completed| {
+                        completed
+                            .iter()
+                            .filter_map(|step| {
+                                Some((
+                                    step["percentage"].as_u64()? as u32,
+                                    step["revision"].as_u64(),
+                                ))
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let rollout = Rollout {
+                    state_path: state_path.to_path_buf(),
+                    release: release.to_string(),
+                    steps: steps.to_vec(),
+                    start_revision: state["start_revision"].as_u64(),
+                    completed,
+                };
+                eprintln!(
+                    "resuming the canary rollout of {} after {} of {} steps",
+                    release,
+                    rollout.completed.len(),
+                    steps.len()
+                );
+                Ok(rollout)
+            }
+            (Some(_), false) => Err(FoilError::Config(format!(
+                "a canary rollout of {} is in progress, see {}; use --resume to carry on with it",
+                release,
+                state_path.display()
+            ))),
+            (None, true) => Err(FoilError::Config(format!(
+                "no interrupted canary rollout of {} to resume in {}",
+                release,
+                state_path.display()
+            ))),
+            (None, false) => {
+                let rollout = Rollout {
+                    state_path: state_path.to_path_buf(),
+                    release: release.to_string(),
+                    steps: steps.to_vec(),
+                    start_revision: client.last_deployed(release)?,
+                    completed: Vec::new(),
+                };
+                rollout.save(IN_PROGRESS)?;
+                Ok(rollout)
+            }
+        }
+    }
+
+    fn step_done(&mut self, percentage: u32, revision: Option<u64>) -> Result<(), FoilError> {
+        self.completed.push((percentage, revision));
+        self.save(IN_PROGRESS)
+    }
+
+    fn finish(&self) -> Result<(), FoilError> {
+        self.save(COMPLETE)
+    }
+
+    /*
+    back to the last step that passed, or to where the release was before the rollout
+    */
+    fn roll_back(&self, client: &HelmClient) -> Result<(), FoilError> {
+        let target = self
+            .completed
+            .iter()
+            .rev()
+            .find_map(|(percentage, revision)| {
+                revision.map(|revision| (Some(*percentage), revision))
+            })
+            .or_else(|| self.start_revision.map(|revision| (None, revision)));
+
+        match target {
+            Some((percentage, revision)) => {
+                let result = client.rollback(&self.release, revision);
+                match (&result, percentage) {
+                    (Ok(_), Some(percentage)) => eprintln!(
+                        "rolled {} back to revision {}, the {}% step",
+                        self.release, revision, percentage
+                    ),
+                    (Ok(_), None) => eprintln!(
+                        "rolled {} back to revision {}, from before the rollout",
+                        self.release, revision
+                    ),
+                    (Err(e), _) => eprintln!(
+                        "rolling {} back to revision {} failed too: {}",
+                        self.release, revision, e
+                    ),
+                }
+                if result.is_ok() {
+                    self.save(ROLLED_BACK)?;
+                }
+            }
+            None => eprintln!(
+                "{} has no earlier revision to roll back to, it is left as is",
+                self.release
+            ),
+        }
+        Ok(())
+    }
+
+    fn save(&self, status: &str) -> Result<(), FoilError> {
+        let completed: Vec<serde_json::Value> = self
+            .completed
+            .iter()
+            .map(|(percentage, revision)| json!({ "percentage": percentage, "revision": revision }))
+            .collect();
+        statefile::save(
+            &self.state_path,
+            &json!({
+                "release": self.release,
+                "steps": self.steps,
+                "start_revision": self.start_revision,
+                "completed": completed,
+                "status": status,
+            }),
+        )
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::foilerror::FoilError;
+use crate::templatevalue::Value;
+
+// Chart.yaml keys and the names helm gives them under .Chart
+const FIELDS: &[(&str, &str)] = &[
+    ("apiVersion", "APIVersion"),
+    ("name", "Name"),
+    ("version", "Version"),
+    ("kubeVersion", "KubeVersion"),
+    ("description", "Description"),
+    ("type", "Type"),
+    ("keywords", "Keywords"),
+    ("home", "Home"),
+    ("sources", "Sources"),
+    ("dependencies", "Dependencies"),
+    ("maintainers", "Maintainers"),
+    ("icon", "Icon"),
+    ("appVersion", "AppVersion"),
+    ("deprecated", "Deprecated"),
+    ("annotations", "Annotations"),
+];
+
+const DEPENDENCY_FIELDS: &[(&str, &str)] = &[
+    ("name", "Name"),
+    ("version", "Version"),
+    ("repository", "Repository"),
+    ("condition", "Condition"),
+    ("tags", "Tags"),
+    ("enabled", "Enabled"),
+    ("import-values", "ImportValues"),
+    ("alias", "Alias"),
+];
+
+/**
+The chart's Chart.yaml as the `.Chart` variables helm templates see: `.Chart.Name`,
+`.Chart.Version`, `.Chart.AppVersion`, `.Chart.Annotations.*`, ... `None` when the chart has no
+Chart.yaml. Helm 2 charts list their dependencies in requirements.yaml, those are used when
+Chart.yaml has none.
+**/
+pub(crate) fn load(chart_dir: &Path) -> Result<Option<Value>, FoilError> {
+    let chart = match read_yaml(&chart_dir.join("Chart.yaml"))? {
+        Some(Value::Map(chart)) => chart,
+        Some(_) => {
+            return Err(FoilError::Config(format!(
+                "{} is not a map",
+                chart_dir.join("Chart.yaml").display()
+            )))
+        }
+        None => return Ok(None),
+    };
+
+    let mut metadata = rename(chart, FIELDS);
+    if !metadata.contains_key("Dependencies") {
+        if let Some(Value::Map(requirements)) = read_yaml(&chart_dir.join("requirements.yaml"))? {
+            if let Some(dependencies) = requirements.get("dependencies") {
+                metadata.insert("Dependencies".to_string(), dependencies.clone());
+            }
+        }
+    }
+    if let Some(Value::List(dependencies)) = metadata.get_mut("Dependencies") {
+        for dependency in dependencies.iter_mut() {
+            if let Value::Map(fields) = dependency {
+                *dependency = Value::Map(rename(std::mem::take(fields), DEPENDENCY_FIELDS));
+            }
+        }
+    }
+
+    Ok(Some(Value::Map(metadata)))
+}
+
+fn read_yaml(path: &Path) -> Result<Option<Value>, FoilError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(FoilError::io(path, e)),
+    };
+    serde_yaml::from_str::<serde_yaml::Value>(&contents)
+        .map(|yaml| Some(Value::from(yaml)))
+        .map_err(|e| FoilError::Config(format!("error in {}: {}", path.display(), e)))
+}
+
+/*
+known keys get helm's capitalised names, anything else is kept as written
+*/
+fn rename(fields: BTreeMap<String, Value>, names: &[(&str, &str)]) -> BTreeMap<String, Value> {
+    fields
+        .into_iter()
+        .map(|(key, value)| {
+            let renamed = names
+                .iter()
+                .find(|(yaml_key, _)| *yaml_key == key)
+                .map(|(_, name)| name.to_string())
+                .unwrap_or(key);
+            (renamed, value)
+        })
+        .collect()
+}
@@ -0,0 +1,28 @@
+use std::process::Command as ProcessCommand;
+
+use crate::foilerror::FoilError;
+
+/**
+Run a workflow's `--check` command through the shell once helm is done. It decides whether the
+release is healthy: exit status 0 is healthy, anything else fails the step. The `HELM_FOIL_*`
+variables tell the command what was just deployed.
+**/
+pub(crate) fn run_check(check: &str, env: &[(&str, String)]) -> Result<(), FoilError> {
+    eprintln!("running check {}", check);
+    let status = ProcessCommand::new("sh")
+        .arg("-c")
+        .arg(check)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .status()
+        .map_err(|source| FoilError::HelmSpawn {
+            binary: "sh".to_string(),
+            source,
+        })?;
+    if status.success() {
+        return Ok(());
+    }
+    Err(FoilError::CheckFailed {
+        command: check.to_string(),
+        code: status.code().unwrap_or(-1),
+    })
+}
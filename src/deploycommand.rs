@@ -0,0 +1,129 @@
+use crate::command::Command;
+use crate::foilerror::FoilError;
+use crate::helmclient::{HelmClient, Revision};
+use crate::helmruntime::HelmRuntime;
+use crate::helmversion::HelmVersion;
+use crate::installcommand;
+use crate::upgradecommand;
+use clap::ArgMatches;
+
+/**
+Install or upgrade, whichever the release needs, so a pipeline doesn't have to know whether it
+has deployed before. `helm history` is asked once; Helm 3 then gets `upgrade --install`, Helm 2
+an install for a new release and an upgrade for an existing one. A Helm 2 release whose first
+install never succeeded can't be upgraded, with `--recover` it is purged and installed again.
+**/
+pub(crate) struct DeployCommand<'a> {
+    helm_runtime: &'a mut HelmRuntime,
+}
+
+impl<'a> DeployCommand<'a> {
+    pub(crate) fn new(execute_helm_command: &'a mut HelmRuntime) -> DeployCommand<'a> {
+        DeployCommand {
+            helm_runtime: execute_helm_command,
+        }
+    }
+}
+
+impl<'a> Command for DeployCommand<'a> {
+    fn get_helm_runtime(&mut self) -> &mut HelmRuntime {
+        self.helm_runtime
+    }
+
+    fn execute(
+        &mut self,
+        matches: &ArgMatches,
+        commandline: &Option<&str>,
+        helm_binary: String,
+    ) -> Result<(), FoilError> {
+        if let Some(command) = commandline {
+            if let Some(deploy_command) = matches.subcommand_matches(command) {
+                let release = self
+                    .get_helm_runtime()
+                    .release_name(deploy_command.value_of("RELEASE"))?
+                    .unwrap_or_default();
+                let client = HelmClient::new(&helm_binary, self.get_helm_runtime(), matches);
+                let history = client.history(&release)?;
+                let helm_runtime = self.get_helm_runtime();
+
+                let mut helm_command = if helm_runtime.helm_version() == HelmVersion::V3 {
+                    eprintln!(
+                        "deploy: upgrade --install {}, {}",
+                        release,
+                        describe(&history)
+                    );
+                    let mut helm_command = upgradecommand::prepare_upgrade(
+                        helm_runtime,
+                        matches,
+                        deploy_command,
+                        &helm_binary,
+                    )?;
+                    helm_command.arg("--install");
+                    helm_command
+                } else if history.is_empty() {
+                    eprintln!("deploy: install {}, {}", release, describe(&history));
+                    installcommand::prepare_install(
+                        helm_runtime,
+                        matches,
+                        deploy_command,
+                        &helm_binary,
+                        Some(&release),
+                    )?
+                } else if is_stuck(&history) {
+                    if !deploy_command.is_present("recover") {
+                        return Err(FoilError::Config(format!(
+                            "{} is stuck, {}; use --recover to purge it and install again",
+                            release,
+                            describe(&history)
+                        )));
+                    }
+                    eprintln!(
+                        "deploy: purge and install {}, {}",
+                        release,
+                        describe(&history)
+                    );
+                    // rendered first, a values file that fails leaves the release as it was
+                    let helm_command = installcommand::prepare_install(
+                        helm_runtime,
+                        matches,
+                        deploy_command,
+                        &helm_binary,
+                        Some(&release),
+                    )?;
+                    client.delete(&release)?;
+                    helm_command
+                } else {
+                    eprintln!("deploy: upgrade {}, {}", release, describe(&history));
+                    upgradecommand::prepare_upgrade(
+                        helm_runtime,
+                        matches,
+                        deploy_command,
+                        &helm_binary,
+                    )?
+                };
+                return helm_runtime.execute_helm(&mut helm_command);
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+a release that never got deployed: Helm 2 refuses to upgrade it and to install over it
+*/
+fn is_stuck(history: &[Revision]) -> bool {
+    match history.last() {
+        Some(latest) if latest.status == "pending-install" => true,
+        Some(latest) if latest.status == "failed" => !history
+            .iter()
+            .any(|revision| revision.status == "deployed" || revision.status == "superseded"),
+        _ => false,
+    }
+}
+
+fn describe(history: &[Revision]) -> String {
+    match history.last() {
+        Some(latest) => format!("revision {} is {}", latest.revision, latest.status),
+        None => "no release yet".to_string(),
+    }
+}
@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+/**
+A duration the way helm writes its timeouts: `90s`, `5m`, `1h30m`, or a plain number of seconds.
+**/
+pub(crate) fn parse(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    if let Ok(seconds) = text.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    let mut total = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(format!("{} is not a duration like 30s, 5m or 1h", text)),
+        };
+        let value: u64 = number
+            .parse()
+            .map_err(|_| format!("{} is not a duration like 30s, 5m or 1h", text))?;
+        total += value * unit;
+        number.clear();
+    }
+    if !number.is_empty() || text.is_empty() {
+        return Err(format!("{} is not a duration like 30s, 5m or 1h", text));
+    }
+    Ok(Duration::from_secs(total))
+}
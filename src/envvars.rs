@@ -0,0 +1,74 @@
+use std::env;
+
+use crate::templatevalue::Value;
+
+/**
+The environment variables templates may read as `{{ .Env.NAME }}` or `{{ env "NAME" }}`. Only
+names matching one of the `--allow-env` globs are exposed, nothing by default, so a values file
+can't copy arbitrary secrets from the CI environment into the cluster.
+**/
+pub(crate) fn allowed_env(patterns: &[String]) -> Value {
+    let mut allowed = Value::new_map();
+    if patterns.is_empty() {
+        return allowed;
+    }
+    if let Value::Map(map) = &mut allowed {
+        for (name, value) in env::vars() {
+            if patterns.iter().any(|pattern| glob_match(pattern, &name)) {
+                map.insert(name, Value::from(value));
+            }
+        }
+    }
+    allowed
+}
+
+/*
+`*` matches any run of characters and `?` any single one, everything else matches itself
+*/
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // where the last * was and how much of the name it has swallowed so far
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, swallowed)) => {
+                    p = star + 1;
+                    n = swallowed + 1;
+                    backtrack = Some((star, swallowed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("BUILD_*", "BUILD_NUMBER"));
+        assert!(glob_match("BUILD_*", "BUILD_"));
+        assert!(!glob_match("BUILD_*", "XBUILD_NUMBER"));
+        assert!(glob_match("DEPLOY_USER", "DEPLOY_USER"));
+        assert!(!glob_match("DEPLOY_USER", "DEPLOY_USERS"));
+        assert!(glob_match("CI_?", "CI_1"));
+        assert!(!glob_match("CI_?", "CI_12"));
+        assert!(glob_match("*_TOKEN_*", "GH_TOKEN_RO"));
+        assert!(glob_match("*", "ANYTHING"));
+    }
+}
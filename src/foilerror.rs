@@ -0,0 +1,306 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use serde_json::json;
+
+use crate::template::{TemplateError, Unresolved};
+
+/**
+Everything that can stop foil. Each variant has its own process exit code so CI scripts can tell
+a bad command line from a broken template or a failed deploy:
+
+| variant             | exit code                         |
+|---------------------|-----------------------------------|
+| `Usage`             | 64                                |
+| `Config`            | 78                                |
+| `Io`                | 74                                |
+| `TemplateParse`     | 65                                |
+| `Unresolved`        | 66                                |
+| `HelmSpawn`         | 69                                |
+| `HelmFailed`        | helm's own exit code              |
+| `CheckFailed`       | 75                                |
+**/
+#[derive(Debug)]
+pub(crate) enum FoilError {
+    // clap couldn't parse the command line
+    Usage(String),
+    // bad or missing command line / environment settings
+    Config(String),
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    TemplateParse {
+        filename: String,
+        error: TemplateError,
+    },
+    // strict mode found placeholders that did not resolve
+    Unresolved {
+        references: Vec<(String, Unresolved)>,
+        available: Vec<String>,
+    },
+    HelmSpawn {
+        binary: String,
+        source: io::Error,
+    },
+    HelmFailed {
+        code: i32,
+    },
+    // a deploy check command given to a workflow reported the release unhealthy
+    CheckFailed {
+        command: String,
+        code: i32,
+    },
+}
+
+impl FoilError {
+    pub(crate) fn io(path: impl Into<PathBuf>, source: io::Error) -> FoilError {
+        FoilError::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub(crate) fn exit_code(&self) -> i32 {
+        match self {
+            FoilError::Usage(_) => 64,
+            FoilError::Config(_) => 78,
+            FoilError::Io { .. } => 74,
+            FoilError::TemplateParse { .. } => 65,
+            FoilError::Unresolved { .. } => 66,
+            FoilError::HelmSpawn { .. } => 69,
+            FoilError::HelmFailed { code } => *code,
+            FoilError::CheckFailed { .. } => 75,
+        }
+    }
+
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            FoilError::Usage(_) => "usage",
+            FoilError::Config(_) => "config",
+            FoilError::Io { .. } => "io",
+            FoilError::TemplateParse { .. } => "template",
+            FoilError::Unresolved { .. } => "unresolved",
+            FoilError::HelmSpawn { .. } => "helm_spawn",
+            FoilError::HelmFailed { .. } => "helm_failed",
+            FoilError::CheckFailed { .. } => "check_failed",
+        }
+    }
+
+    /*
+    print the error to stderr, as a single JSON object when `--output json` was given
+    */
+    pub(crate) fn report(&self, json_output: bool) {
+        if json_output {
+            eprintln!("{}", self.to_json());
+            return;
+        }
+
+        eprintln!("[helm] {}", self);
+        if let FoilError::Unresolved {
+            references,
+            available,
+        } = self
+        {
+            for (filename, unresolved) in references {
+                eprintln!(
+                    "  {}:{}:{}: {}",
+                    filename, unresolved.line, unresolved.column, unresolved.reference
+                );
+            }
+            eprintln!("[helm] available variables: {}", available.join(", "));
+            eprintln!("[helm] set the missing variables or use --lenient to pass them through");
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut report = json!({
+            "error": self.kind(),
+            "message": self.to_string(),
+            "exit_code": self.exit_code(),
+        });
+        match self {
+            FoilError::Io { path, .. } => {
+                report["path"] = json!(path.display().to_string());
+            }
+            FoilError::TemplateParse { filename, error } => {
+                report["file"] = json!(filename);
+                report["line"] = json!(error.line);
+                report["column"] = json!(error.column);
+            }
+            FoilError::Unresolved {
+                references,
+                available,
+            } => {
+                report["unresolved"] = references
+                    .iter()
+                    .map(|(filename, unresolved)| {
+                        json!({
+                            "reference": unresolved.reference,
+                            "file": filename,
+                            "line": unresolved.line,
+                            "column": unresolved.column,
+                        })
+                    })
+                    .collect();
+                report["available"] = json!(available);
+            }
+            FoilError::CheckFailed { command, code } => {
+                report["check"] = json!(command);
+                report["check_exit_code"] = json!(code);
+            }
+            _ => {}
+        }
+        report
+    }
+}
+
+impl fmt::Display for FoilError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FoilError::Usage(message) | FoilError::Config(message) => write!(f, "{}", message),
+            FoilError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            FoilError::TemplateParse { filename, error } => {
+                write!(f, "error in template {}:{}", filename, error)
+            }
+            FoilError::Unresolved { references, .. } => {
+                write!(f, "{} unresolved template variables", references.len())
+            }
+            FoilError::HelmSpawn { binary, source } => {
+                write!(f, "failed to run {}: {}", binary, source)
+            }
+            FoilError::HelmFailed { code } => write!(f, "helm exited with status {}", code),
+            FoilError::CheckFailed { command, code } => {
+                write!(f, "check `{}` exited with status {}", command, code)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io_error() -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, "not found")
+    }
+
+    #[test]
+    fn exit_codes() {
+        let errors = vec![
+            (FoilError::Usage("bad".to_string()), 64, "usage"),
+            (FoilError::Config("bad".to_string()), 78, "config"),
+            (FoilError::io("values.yaml", io_error()), 74, "io"),
+            (
+                FoilError::TemplateParse {
+                    filename: "values.yaml".to_string(),
+                    error: TemplateError {
+                        message: "unclosed action".to_string(),
+                        line: 1,
+                        column: 1,
+                    },
+                },
+                65,
+                "template",
+            ),
+            (
+                FoilError::Unresolved {
+                    references: Vec::new(),
+                    available: Vec::new(),
+                },
+                66,
+                "unresolved",
+            ),
+            (
+                FoilError::HelmSpawn {
+                    binary: "helm".to_string(),
+                    source: io_error(),
+                },
+                69,
+                "helm_spawn",
+            ),
+            (FoilError::HelmFailed { code: 3 }, 3, "helm_failed"),
+            (
+                FoilError::CheckFailed {
+                    command: "curl".to_string(),
+                    code: 7,
+                },
+                75,
+                "check_failed",
+            ),
+        ];
+        for (error, code, kind) in errors {
+            assert_eq!((error.exit_code(), error.kind()), (code, kind), "{}", error);
+        }
+    }
+
+    #[test]
+    fn json_reports() {
+        let error = FoilError::TemplateParse {
+            filename: "values.yaml".to_string(),
+            error: TemplateError {
+                message: "unclosed action".to_string(),
+                line: 2,
+                column: 4,
+            },
+        };
+        assert_eq!(
+            error.to_json(),
+            json!({
+                "error": "template",
+                "message": "error in template values.yaml:2:4: unclosed action",
+                "exit_code": 65,
+                "file": "values.yaml",
+                "line": 2,
+                "column": 4,
+            })
+        );
+
+        let error = FoilError::Unresolved {
+            references: vec![(
+                "values.yaml".to_string(),
+                Unresolved {
+                    reference: ".Git.Tag".to_string(),
+                    line: 3,
+                    column: 7,
+                },
+            )],
+            available: vec!["Release.Name".to_string()],
+        };
+        assert_eq!(
+            error.to_json(),
+            json!({
+                "error": "unresolved",
+                "message": "1 unresolved template variables",
+                "exit_code": 66,
+                "unresolved": [
+                    {"reference": ".Git.Tag", "file": "values.yaml", "line": 3, "column": 7},
+                ],
+                "available": ["Release.Name"],
+            })
+        );
+
+        let error = FoilError::io("values.yaml", io_error());
+        assert_eq!(error.to_json()["path"], "values.yaml");
+        assert_eq!(error.to_json()["message"], "values.yaml: not found");
+
+        let error = FoilError::CheckFailed {
+            command: "curl -f x".to_string(),
+            code: 22,
+        };
+        assert_eq!(error.to_json()["check"], "curl -f x");
+        assert_eq!(error.to_json()["check_exit_code"], 22);
+        assert_eq!(error.to_json()["exit_code"], 75);
+
+        let error = FoilError::HelmFailed { code: 2 };
+        assert_eq!(
+            error.to_json(),
+            json!({
+                "error": "helm_failed",
+                "message": "helm exited with status 2",
+                "exit_code": 2,
+            })
+        );
+    }
+}
@@ -0,0 +1,461 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command as ProcessCommand, Stdio};
+
+/**
+Branch and commit information for the repository that holds the chart. Everything except the
+dirty flag and the nearest tag is read straight from the files under `.git`, so it works offline
+and without a git binary; when `git` is on the PATH it is asked for those two as well.
+Without git the tag is only known when a tag points at HEAD itself, and the dirty flag not at
+all; both are then left unset, so strict mode fails on them rather than rendering a tag from
+further back in the history than git would pick.
+
+CI systems usually check out a detached HEAD, so their branch and commit environment variables
+win over what `.git` says when they are set.
+**/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct GitContext {
+    pub(crate) branch: Option<String>,
+    pub(crate) previous_branch: Option<String>,
+    pub(crate) sha: Option<String>,
+    pub(crate) tag: Option<String>,
+    pub(crate) dirty: Option<bool>,
+}
+
+// checked in order, the first one that is set and not empty names the branch
+const CI_BRANCH_VARS: &[&str] = &[
+    "GITHUB_HEAD_REF",
+    "CI_COMMIT_REF_NAME",
+    "BRANCH_NAME",
+    "GIT_BRANCH",
+    "BUILDKITE_BRANCH",
+    "CIRCLE_BRANCH",
+    "TRAVIS_BRANCH",
+    "GITHUB_REF_NAME",
+];
+
+const CI_SHA_VARS: &[&str] = &[
+    "GITHUB_SHA",
+    "CI_COMMIT_SHA",
+    "GIT_COMMIT",
+    "BUILDKITE_COMMIT",
+    "CIRCLE_SHA1",
+    "TRAVIS_COMMIT",
+];
+
+const CI_TAG_VARS: &[&str] = &["CI_COMMIT_TAG", "CIRCLE_TAG", "TRAVIS_TAG"];
+
+impl GitContext {
+    /*
+    git information for the repository containing `start`, or failing that the working directory,
+    with the CI overrides applied
+    */
+    pub(crate) fn discover(start: &Path) -> GitContext {
+        let work_dir = env::current_dir().ok();
+        let candidates = Some(start).into_iter().chain(work_dir.as_deref());
+        let found = candidates
+            .filter_map(|dir| find_git_dir(dir).map(|git_dir| (git_dir, dir)))
+            .next();
+
+        let mut context = match found {
+            Some((git_dir, dir)) => GitContext::read(&git_dir, dir),
+            None => GitContext::default(),
+        };
+        context.apply_ci_env(|name| env::var(name).ok());
+        context
+    }
+
+    fn read(git_dir: &Path, work_dir: &Path) -> GitContext {
+        let mut context = GitContext::read_files(git_dir);
+        // only git itself can walk the history to the nearest tag and compare the work tree
+        if let Some(tag) = context
+            .sha
+            .as_deref()
+            .and_then(|sha| git_output(work_dir, &["describe", "--tags", "--abbrev=0", sha]))
+        {
+            context.tag = Some(tag);
+        }
+        context.dirty = git_output(work_dir, &["status", "--porcelain"]).map(|out| !out.is_empty());
+        context
+    }
+
+    /*
+    what the files under `.git` tell without running git
+    */
+    fn read_files(git_dir: &Path) -> GitContext {
+        let common_dir = common_dir(git_dir);
+        let head = read_trimmed(&git_dir.join("HEAD"));
+
+        let (branch, sha) = match head.as_deref().and_then(|h| h.strip_prefix("ref: ")) {
+            Some(reference) => (
+                reference
+                    .strip_prefix("refs/heads/")
+                    .map(|name| name.to_string()),
+                resolve_ref(&common_dir, git_dir, reference),
+            ),
+            // detached HEAD holds the commit itself
+            None => (None, head),
+        };
+
+        let tag = sha.as_deref().and_then(|sha| tag_at(&common_dir, sha));
+
+        GitContext {
+            branch,
+            previous_branch: previous_branch(git_dir),
+            sha,
+            tag,
+            dirty: None,
+        }
+    }
+
+    fn apply_ci_env(&mut self, env: impl Fn(&str) -> Option<String>) {
+        if let Some(branch) = first_env(CI_BRANCH_VARS, &env) {
+            // Jenkins reports origin/feature, GitHub refs/heads/feature on push builds
+            let branch = branch
+                .trim_start_matches("refs/heads/")
+                .trim_start_matches("origin/");
+            self.branch = Some(branch.to_string());
+        }
+        if let Some(sha) = first_env(CI_SHA_VARS, &env) {
+            self.sha = Some(sha);
+        }
+        if let Some(tag) = first_env(CI_TAG_VARS, &env) {
+            self.tag = Some(tag);
+        }
+    }
+
+    pub(crate) fn short_sha(&self) -> Option<String> {
+        self.sha
+            .as_ref()
+            .map(|sha| sha.chars().take(7).collect::<String>())
+    }
+}
+
+/*
+every branch of the repository containing `start`, or the working directory: local branches and
+remote-tracking ones without their remote's name. `None` when there is no repository
+*/
+pub(crate) fn branches(start: &Path) -> Option<Vec<String>> {
+    let work_dir = env::current_dir().ok();
+    let git_dir = Some(start)
+        .into_iter()
+        .chain(work_dir.as_deref())
+        .find_map(find_git_dir)?;
+    let common_dir = common_dir(&git_dir);
+
+    let mut refs: Vec<String> = packed_refs(&common_dir)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    collect_refs(&common_dir.join("refs"), "refs", &mut refs);
+
+    let mut branches: Vec<String> = refs
+        .iter()
+        .filter_map(|name| {
+            name.strip_prefix("refs/heads/").or_else(|| {
+                name.strip_prefix("refs/remotes/")
+                    .and_then(|remote_branch| remote_branch.split_once('/'))
+                    .map(|(_, branch)| branch)
+                    .filter(|branch| *branch != "HEAD")
+            })
+        })
+        .map(|branch| branch.to_string())
+        .collect();
+    branches.sort();
+    branches.dedup();
+    Some(branches)
+}
+
+/*
+loose refs under `dir`, branch names may contain slashes so this walks subdirectories
+*/
+fn collect_refs(dir: &Path, prefix: &str, refs: &mut Vec<String>) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+            if entry.path().is_dir() {
+                collect_refs(&entry.path(), &name, refs);
+            } else {
+                refs.push(name);
+            }
+        }
+    }
+}
+
+fn first_env(names: &[&str], env: impl Fn(&str) -> Option<String>) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| env(name))
+        .find(|value| !value.is_empty())
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|contents| contents.trim().to_string())
+        .filter(|contents| !contents.is_empty())
+}
+
+/*
+walk up from `start` to the first directory with a .git; a .git file (worktrees, submodules)
+points at the real git directory
+*/
+fn find_git_dir(start: &Path) -> Option<PathBuf> {
+    let start = fs::canonicalize(start).ok()?;
+    for dir in start.ancestors() {
+        let dot_git = dir.join(".git");
+        if dot_git.is_dir() {
+            return Some(dot_git);
+        }
+        if dot_git.is_file() {
+            let gitdir = read_trimmed(&dot_git)?;
+            let gitdir = Path::new(gitdir.strip_prefix("gitdir:")?.trim());
+            return Some(dir.join(gitdir));
+        }
+    }
+    None
+}
+
+/*
+linked worktrees keep their own HEAD and reflog but share refs with the main repository
+*/
+fn common_dir(git_dir: &Path) -> PathBuf {
+    match read_trimmed(&git_dir.join("commondir")) {
+        Some(common) => git_dir.join(common),
+        None => git_dir.to_path_buf(),
+    }
+}
+
+fn resolve_ref(common_dir: &Path, git_dir: &Path, reference: &str) -> Option<String> {
+    read_trimmed(&git_dir.join(reference))
+        .or_else(|| read_trimmed(&common_dir.join(reference)))
+        .or_else(|| {
+            packed_refs(common_dir)
+                .into_iter()
+                .find(|(name, _)| name == reference)
+                .map(|(_, sha)| sha)
+        })
+}
+
+/*
+(ref name, commit) pairs from packed-refs; annotated tags are reported with the commit they peel to
+*/
+fn packed_refs(common_dir: &Path) -> Vec<(String, String)> {
+    let contents = fs::read_to_string(common_dir.join("packed-refs")).unwrap_or_default();
+    let mut refs: Vec<(String, String)> = Vec::new();
+    for line in contents.lines() {
+        if let Some(peeled) = line.strip_prefix('^') {
+            if let Some(last) = refs.last_mut() {
+                last.1 = peeled.to_string();
+            }
+        } else if !line.starts_with('#') {
+            if let Some((sha, name)) = line.split_once(' ') {
+                refs.push((name.to_string(), sha.to_string()));
+            }
+        }
+    }
+    refs
+}
+
+/*
+a tag pointing straight at the commit, used when git isn't available to describe the history;
+the newest by name when there are several
+*/
+fn tag_at(common_dir: &Path, sha: &str) -> Option<String> {
+    let mut tags: Vec<String> = packed_refs(common_dir)
+        .into_iter()
+        .filter(|(name, commit)| name.starts_with("refs/tags/") && commit == sha)
+        .map(|(name, _)| name["refs/tags/".len()..].to_string())
+        .collect();
+
+    if let Ok(entries) = fs::read_dir(common_dir.join("refs").join("tags")) {
+        for entry in entries.flatten() {
+            if read_trimmed(&entry.path()).as_deref() == Some(sha) {
+                if let Some(name) = entry.file_name().to_str() {
+                    tags.push(name.to_string());
+                }
+            }
+        }
+    }
+    tags.sort();
+    tags.pop()
+}
+
+/*
+the branch HEAD was on before the last checkout, the same one `git checkout -` goes back to
+*/
+fn previous_branch(git_dir: &Path) -> Option<String> {
+    let reflog = fs::read_to_string(git_dir.join("logs").join("HEAD")).ok()?;
+    reflog.lines().rev().find_map(|line| {
+        let (_, message) = line.split_once('\t')?;
+        let moved = message.strip_prefix("checkout: moving from ")?;
+        let (from, _) = moved.rsplit_once(" to ")?;
+        Some(from.to_string())
+    })
+}
+
+fn git_output(work_dir: &Path, args: &[&str]) -> Option<String> {
+    let output = ProcessCommand::new("git")
+        .arg("-C")
+        .arg(work_dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if output.status.success() {
+        String::from_utf8(output.stdout)
+            .ok()
+            .map(|out| out.trim().to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    const SHA: &str = "8be9bb9762e6f1b0bd30e32403c93fa31db4e657";
+    const OLDER: &str = "0fd5c4564d88f54825f8a89f4642326dc6a8e207";
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    // a repository on feature/x that came from master, with a packed tag on HEAD
+    fn repository(dir: &Path) -> PathBuf {
+        let git_dir = dir.join(".git");
+        write(&git_dir.join("HEAD"), "ref: refs/heads/feature/x\n");
+        write(&git_dir.join("refs/heads/feature/x"), &format!("{}\n", SHA));
+        write(
+            &git_dir.join("packed-refs"),
+            &format!(
+                "# pack-refs with: peeled fully-peeled sorted\n\
+                 {older} refs/heads/master\n\
+                 {older} refs/tags/v1.0.0\n\
+                 1111111111111111111111111111111111111111 refs/tags/v1.1.0\n\
+                 ^{sha}\n",
+                older = OLDER,
+                sha = SHA
+            ),
+        );
+        write(
+            &git_dir.join("logs/HEAD"),
+            &format!(
+                "0000000000000000000000000000000000000000 {older} A <a@b> 1 +0000\tclone: from x\n\
+                 {older} {older} A <a@b> 2 +0000\tcheckout: moving from master to release/1\n\
+                 {older} {sha} A <a@b> 3 +0000\tcheckout: moving from release/1 to feature/x\n\
+                 {sha} {sha} A <a@b> 4 +0000\tcommit: more\n",
+                older = OLDER,
+                sha = SHA
+            ),
+        );
+        git_dir
+    }
+
+    #[test]
+    fn reads_head_reflog_and_packed_refs() {
+        let dir = tempfile::tempdir().unwrap();
+        let git_dir = repository(dir.path());
+
+        let context = GitContext::read_files(&git_dir);
+        assert_eq!(context.branch.as_deref(), Some("feature/x"));
+        assert_eq!(context.previous_branch.as_deref(), Some("release/1"));
+        assert_eq!(context.sha.as_deref(), Some(SHA));
+        assert_eq!(context.short_sha().as_deref(), Some("8be9bb9"));
+        // the annotated tag peels to HEAD
+        assert_eq!(context.tag.as_deref(), Some("v1.1.0"));
+        assert_eq!(context.dirty, None);
+    }
+
+    #[test]
+    fn reads_a_packed_branch_and_a_detached_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let git_dir = repository(dir.path());
+
+        write(&git_dir.join("HEAD"), "ref: refs/heads/master\n");
+        let context = GitContext::read_files(&git_dir);
+        assert_eq!(context.branch.as_deref(), Some("master"));
+        assert_eq!(context.sha.as_deref(), Some(OLDER));
+        assert_eq!(context.tag.as_deref(), Some("v1.0.0"));
+
+        write(&git_dir.join("HEAD"), &format!("{}\n", OLDER));
+        let context = GitContext::read_files(&git_dir);
+        assert_eq!(context.branch, None);
+        assert_eq!(context.sha.as_deref(), Some(OLDER));
+    }
+
+    #[test]
+    fn leaves_the_tag_unset_without_a_tag_on_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let git_dir = repository(dir.path());
+        let newest = "2222222222222222222222222222222222222222";
+        write(&git_dir.join("refs/heads/feature/x"), newest);
+
+        let context = GitContext::read_files(&git_dir);
+        assert_eq!(context.sha.as_deref(), Some(newest));
+        assert_eq!(context.tag, None);
+    }
+
+    #[test]
+    fn follows_a_linked_worktree_to_the_shared_refs() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main");
+        let git_dir = repository(&main);
+        let worktree_git_dir = git_dir.join("worktrees/hotfix");
+        write(&worktree_git_dir.join("HEAD"), "ref: refs/heads/master\n");
+        write(&worktree_git_dir.join("commondir"), "../..\n");
+        write(
+            &worktree_git_dir.join("logs/HEAD"),
+            "x y A <a@b> 1 +0000\tcheckout: moving from feature/x to master\n",
+        );
+        let worktree = dir.path().join("hotfix");
+        write(
+            &worktree.join(".git"),
+            &format!("gitdir: {}\n", worktree_git_dir.display()),
+        );
+        fs::create_dir_all(worktree.join("chart")).unwrap();
+
+        let found = find_git_dir(&worktree.join("chart")).unwrap();
+        assert_eq!(
+            fs::canonicalize(&found).unwrap(),
+            fs::canonicalize(&worktree_git_dir).unwrap()
+        );
+        let context = GitContext::read_files(&found);
+        assert_eq!(context.branch.as_deref(), Some("master"));
+        assert_eq!(context.previous_branch.as_deref(), Some("feature/x"));
+        assert_eq!(context.sha.as_deref(), Some(OLDER));
+        assert_eq!(context.tag.as_deref(), Some("v1.0.0"));
+
+        let branches = branches(&worktree).unwrap();
+        assert_eq!(branches, vec!["feature/x", "master"]);
+    }
+
+    #[test]
+    fn ci_environment_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut context = GitContext::read_files(&repository(dir.path()));
+        let env: HashMap<&str, &str> = [
+            ("GIT_BRANCH", "origin/jenkins"),
+            ("GITHUB_HEAD_REF", ""),
+            ("CI_COMMIT_SHA", "abcdef0123"),
+            ("CI_COMMIT_TAG", "v2.0.0"),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+
+        context.apply_ci_env(|name| env.get(name).map(|value| value.to_string()));
+        assert_eq!(context.branch.as_deref(), Some("jenkins"));
+        assert_eq!(context.sha.as_deref(), Some("abcdef0123"));
+        assert_eq!(context.short_sha().as_deref(), Some("abcdef0"));
+        assert_eq!(context.tag.as_deref(), Some("v2.0.0"));
+        // the reflog is still read from .git
+        assert_eq!(context.previous_branch.as_deref(), Some("release/1"));
+    }
+}
@@ -0,0 +1,205 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::foilerror::FoilError;
+
+/**
+Where the helm binary foil runs was found, in the order it is looked for: `--helm-binary`,
+`HELM_FOIL_HELM_BIN`, `HELM_BIN`, the `PATH` and finally `$HELM_HOME/helm`, which is where
+older setups kept it.
+**/
+#[derive(Debug, Clone)]
+pub(crate) struct HelmBinary {
+    pub(crate) path: String,
+    pub(crate) source: &'static str,
+}
+
+impl HelmBinary {
+    pub(crate) fn locate(flag: Option<&str>) -> Result<HelmBinary, FoilError> {
+        let own_exe = env::current_exe().and_then(fs::canonicalize).ok();
+        HelmBinary::locate_in(flag, |name| env::var_os(name), own_exe.as_deref())
+    }
+
+    /*
+    the lookup itself, with the environment and foil's own executable passed in
+    */
+    fn locate_in(
+        flag: Option<&str>,
+        env: impl Fn(&str) -> Option<OsString>,
+        own_exe: Option<&Path>,
+    ) -> Result<HelmBinary, FoilError> {
+        let var = |name: &str| {
+            env(name)
+                .map(|value| value.to_string_lossy().into_owned())
+                .filter(|value| !value.is_empty())
+        };
+        if let Some(path) = flag {
+            return Ok(HelmBinary::new(path, "--helm-binary"));
+        }
+        for variable in &["HELM_FOIL_HELM_BIN", "HELM_BIN"] {
+            if let Some(path) = var(variable) {
+                return Ok(HelmBinary::new(&path, variable));
+            }
+        }
+        if let Some(path) = env("PATH").and_then(|path| search_path(&path, own_exe)) {
+            return Ok(HelmBinary::new(&path.to_string_lossy(), "PATH"));
+        }
+        if let Some(helm_home) = var("HELM_HOME") {
+            return Ok(HelmBinary::new(&format!("{}/helm", helm_home), "HELM_HOME"));
+        }
+        Err(FoilError::Config(
+            "helm not found, use --helm-binary, set HELM_BIN or put helm on the PATH".to_string(),
+        ))
+    }
+
+    fn new(path: &str, source: &'static str) -> HelmBinary {
+        HelmBinary {
+            path: path.to_string(),
+            source,
+        }
+    }
+}
+
+/*
+the first helm on the PATH that isn't foil itself, so `alias helm=helm_foil` or a helm symlink
+to foil doesn't make foil run itself
+*/
+fn search_path(path: &OsString, own_exe: Option<&Path>) -> Option<PathBuf> {
+    let name = format!("helm{}", env::consts::EXE_SUFFIX);
+    env::split_paths(path)
+        .map(|dir| dir.join(&name))
+        .filter(|candidate| is_executable(candidate))
+        .find(|candidate| {
+            own_exe.is_none() || fs::canonicalize(candidate).ok().as_deref() != own_exe
+        })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn executable(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    fn locate(
+        flag: Option<&str>,
+        env: &[(&str, String)],
+        own_exe: Option<&Path>,
+    ) -> Result<HelmBinary, FoilError> {
+        let env: HashMap<&str, OsString> = env
+            .iter()
+            .map(|(name, value)| (*name, OsString::from(value)))
+            .collect();
+        HelmBinary::locate_in(flag, |name| env.get(name).cloned(), own_exe)
+    }
+
+    #[test]
+    fn looks_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("bin");
+        executable(&bin.join("helm"));
+        let bin = bin.to_string_lossy().into_owned();
+        let mut env = vec![
+            ("HELM_HOME", "/opt/helm".to_string()),
+            ("PATH", format!("/nonexistent:{}", bin)),
+            ("HELM_BIN", "/usr/bin/helm3".to_string()),
+            ("HELM_FOIL_HELM_BIN", "/usr/bin/helm2".to_string()),
+        ];
+
+        let found = locate(Some("/bin/helm"), &env, None).unwrap();
+        assert_eq!(
+            (found.path.as_str(), found.source),
+            ("/bin/helm", "--helm-binary")
+        );
+
+        let found = locate(None, &env, None).unwrap();
+        assert_eq!(
+            (found.path.as_str(), found.source),
+            ("/usr/bin/helm2", "HELM_FOIL_HELM_BIN")
+        );
+
+        env.pop();
+        let found = locate(None, &env, None).unwrap();
+        assert_eq!(
+            (found.path.as_str(), found.source),
+            ("/usr/bin/helm3", "HELM_BIN")
+        );
+
+        env.pop();
+        let found = locate(None, &env, None).unwrap();
+        assert_eq!(found.path, format!("{}/helm", bin));
+        assert_eq!(found.source, "PATH");
+
+        env.pop();
+        let found = locate(None, &env, None).unwrap();
+        assert_eq!(
+            (found.path.as_str(), found.source),
+            ("/opt/helm/helm", "HELM_HOME")
+        );
+
+        env.pop();
+        assert!(matches!(
+            locate(None, &env, None),
+            Err(FoilError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn empty_variables_are_skipped() {
+        let env = vec![
+            ("HELM_FOIL_HELM_BIN", String::new()),
+            ("HELM_BIN", String::new()),
+            ("HELM_HOME", "/opt/helm".to_string()),
+        ];
+        let found = locate(None, &env, None).unwrap();
+        assert_eq!(found.source, "HELM_HOME");
+    }
+
+    #[test]
+    fn skips_itself_on_the_path() {
+        let dir = tempfile::tempdir().unwrap();
+        // foil installed as helm, and the real one further down the PATH
+        let own = dir.path().join("foil/helm");
+        executable(&own);
+        let real = dir.path().join("real/helm");
+        executable(&real);
+        // a helm that isn't executable doesn't count either
+        fs::create_dir_all(dir.path().join("plain")).unwrap();
+        fs::write(dir.path().join("plain/helm"), "").unwrap();
+        let path = env::join_paths([
+            own.parent().unwrap(),
+            dir.path().join("plain").as_path(),
+            real.parent().unwrap(),
+        ])
+        .unwrap();
+        let env = vec![("PATH", path.to_string_lossy().into_owned())];
+
+        let own_exe = fs::canonicalize(&own).unwrap();
+        let found = locate(None, &env, Some(&own_exe)).unwrap();
+        assert_eq!(found.path, real.to_string_lossy());
+
+        let found = locate(None, &env, None).unwrap();
+        assert_eq!(found.path, own.to_string_lossy());
+    }
+}
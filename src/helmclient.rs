@@ -0,0 +1,201 @@
+use std::process::{Command as ProcessCommand, Stdio};
+
+use clap::ArgMatches;
+
+use crate::foilerror::FoilError;
+use crate::helmruntime::{self, HelmRuntime};
+use crate::helmversion::HelmVersion;
+
+// forwarded flags that pick the cluster, the queries have to look at the same one
+const CONNECTION_FLAGS: &[&str] = &["--kube-context", "--kubeconfig"];
+// and under Helm 2 the ones that pick tiller and how to talk to it
+const HELM2_CONNECTION_FLAGS: &[&str] = &[
+    "--home",
+    "--host",
+    "--tiller-namespace",
+    "--tls",
+    "--tls-ca-cert",
+    "--tls-cert",
+    "--tls-hostname",
+    "--tls-key",
+    "--tls-verify",
+];
+
+/**
+One revision of a release from `helm history`. The status is in Helm 3's spelling whatever
+helm said it, e.g. `deployed`, `failed` or `pending-install`.
+**/
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Revision {
+    pub(crate) revision: u64,
+    pub(crate) status: String,
+}
+
+/**
+The helm calls the workflows make besides install and upgrade: reading a release's history,
+rolling it back and deleting it, with the same cluster and namespace settings foil was given.
+**/
+#[derive(Debug, Clone)]
+pub(crate) struct HelmClient {
+    binary: String,
+    helm_version: HelmVersion,
+    connection_args: Vec<String>,
+    debug: bool,
+}
+
+impl HelmClient {
+    pub(crate) fn new(
+        binary: &str,
+        helm_runtime: &HelmRuntime,
+        global_args: &ArgMatches,
+    ) -> HelmClient {
+        let helm_version = helm_runtime.helm_version();
+        let mut connection_args = Vec::new();
+        // Helm 2 releases live in tiller's namespace, Helm 3 releases in their own
+        match helm_version {
+            HelmVersion::V2 => {
+                if let Some(tiller_namespace) = global_args.value_of("tiller-namespace") {
+                    connection_args.extend([
+                        "--tiller-namespace".to_string(),
+                        tiller_namespace.to_string(),
+                    ]);
+                }
+            }
+            HelmVersion::V3 => {
+                if let Some(namespace) = global_args.value_of("namespace") {
+                    connection_args.extend(["--namespace".to_string(), namespace.to_string()]);
+                }
+            }
+        }
+        for forwarded in helm_runtime.passthrough_args() {
+            let flag = forwarded.flag();
+            if CONNECTION_FLAGS.contains(&flag)
+                || (helm_version == HelmVersion::V2 && HELM2_CONNECTION_FLAGS.contains(&flag))
+            {
+                connection_args.extend(forwarded.args.iter().cloned());
+            }
+        }
+
+        HelmClient {
+            binary: binary.to_string(),
+            helm_version,
+            connection_args,
+            debug: helm_runtime.debug(),
+        }
+    }
+
+    /*
+    the same client for a release in another namespace; Helm 2 releases aren't namespaced
+    */
+    pub(crate) fn in_namespace(&self, namespace: &str) -> HelmClient {
+        let mut client = self.clone();
+        if self.helm_version == HelmVersion::V3 {
+            // the last --namespace given wins
+            client
+                .connection_args
+                .extend(["--namespace".to_string(), namespace.to_string()]);
+        }
+        client
+    }
+
+    /*
+    every revision of the release, oldest first; empty when the release doesn't exist
+    */
+    pub(crate) fn history(&self, release: &str) -> Result<Vec<Revision>, FoilError> {
+        let output = self
+            .command(&["history", release, "--output", "json"])
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|source| self.spawn_error(source))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.contains("not found") {
+                return Ok(Vec::new());
+            }
+            eprint!("{}", stderr);
+            return helmruntime::check_status(output.status).map(|_| Vec::new());
+        }
+
+        let history: serde_json::Value = serde_json::from_slice(&output.stdout).map_err(|e| {
+            FoilError::Config(format!(
+                "unexpected helm history output for {}: {}",
+                release, e
+            ))
+        })?;
+        let mut revisions: Vec<Revision> = history
+            .as_array()
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|entry| {
+                        Some(Revision {
+                            revision: entry.get("revision")?.as_u64()?,
+                            status: entry
+                                .get("status")?
+                                .as_str()?
+                                .to_lowercase()
+                                .replace('_', "-"),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        revisions.sort_by_key(|revision| revision.revision);
+        Ok(revisions)
+    }
+
+    /*
+    the newest revision, `None` when the release doesn't exist
+    */
+    pub(crate) fn current_revision(&self, release: &str) -> Result<Option<Revision>, FoilError> {
+        Ok(self.history(release)?.pop())
+    }
+
+    /*
+    the newest revision that worked, deployed or superseded by a later one; a failed or pending
+    revision is never one to go back to
+    */
+    pub(crate) fn last_deployed(&self, release: &str) -> Result<Option<u64>, FoilError> {
+        Ok(self
+            .history(release)?
+            .iter()
+            .rev()
+            .find(|revision| revision.status == "deployed" || revision.status == "superseded")
+            .map(|revision| revision.revision))
+    }
+
+    pub(crate) fn rollback(&self, release: &str, revision: u64) -> Result<(), FoilError> {
+        self.run(&["rollback", release, &revision.to_string()])
+    }
+
+    pub(crate) fn delete(&self, release: &str) -> Result<(), FoilError> {
+        match self.helm_version {
+            HelmVersion::V2 => self.run(&["delete", "--purge", release]),
+            HelmVersion::V3 => self.run(&["uninstall", release]),
+        }
+    }
+
+    fn run(&self, args: &[&str]) -> Result<(), FoilError> {
+        let mut helm_command = self.command(args);
+        if self.debug {
+            eprintln!("about to execute {:?}", helm_command);
+        }
+        let status = helm_command
+            .status()
+            .map_err(|source| self.spawn_error(source))?;
+        helmruntime::check_status(status)
+    }
+
+    fn command(&self, args: &[&str]) -> ProcessCommand {
+        let mut helm_command = ProcessCommand::new(&self.binary);
+        helm_command.args(args).args(&self.connection_args);
+        helm_command
+    }
+
+    fn spawn_error(&self, source: std::io::Error) -> FoilError {
+        FoilError::HelmSpawn {
+            binary: self.binary.clone(),
+            source,
+        }
+    }
+}
@@ -1,159 +1,408 @@
-use std::collections::HashMap;
+use std::env;
 use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
 
 use clap::ArgMatches;
-use regex::Regex;
 
-use std::borrow::Borrow;
-use std::fmt::Display;
-use std::fs::File;
-use std::hash::Hash;
-use std::io::Write;
-use std::path::Path;
-use std::process::{Command as ProcessCommand, Output};
-
-#[derive(Debug, Clone)]
+use std::path::{Path, PathBuf};
+use std::process::{Command as ProcessCommand, ExitStatus, Stdio};
+use std::thread;
+
+use crate::chartmeta;
+use crate::envvars;
+use crate::foilerror::FoilError;
+use crate::gitcontext::GitContext;
+use crate::helmversion::{self, HelmVersion};
+use crate::k8sname::{self, RELEASE_NAME_MAX};
+use crate::kubeconfig;
+use crate::passthrough::{self, Forwarded};
+use crate::strvals;
+use crate::template::{Rendered, Template, Unresolved};
+use crate::templatevalue::Value;
+use crate::varsfile;
+use crate::workspace::{self, Workspace};
+
+pub(crate) const STDIN_FILENAME: &str = "-";
+// helm runs post-renderers without foil's command line, the variables reach them through this
+pub(crate) const CONTEXT_ENV: &str = "HELM_FOIL_CONTEXT";
+// and whether it runs strict, "true" or "false"
+pub(crate) const STRICT_ENV: &str = "HELM_FOIL_STRICT";
+// the variables foil sets, in manifests any other {{ .X }} belongs to something else
+const FOIL_ROOTS: &[&str] = &[
+    "Branch", "Canary", "Chart", "Env", "Git", "Live", "Preview", "Previous", "Release",
+    "Starting", "Subchart", "Values", "Vars",
+];
+
+#[derive(Debug)]
 pub(crate) struct HelmRuntime {
-    implicit_variables: HashMap<String, String>,
-    explicit_variables: HashMap<String, String>,
+    // every template variable lives in one tree: .Release.Name, .Chart.Name, .Values.image.tag, ...
+    variables: Value,
+    workspace: Option<Workspace>,
+    // strict mode refuses to hand helm a file with placeholders that did not resolve
+    strict: bool,
+    unresolved: Vec<(String, Unresolved)>,
+    // flags foil doesn't know, forwarded to helm as given
+    passthrough_args: Vec<Forwarded>,
+    // prefix each line helm prints with [helm]
+    prefix_output: bool,
+    // --debug: show the helm command lines and the --set keys
+    debug: bool,
+    // (source file, rendered copy in the workspace) for everything handed to helm
+    rendered_files: Vec<(String, PathBuf)>,
+    // derive a Kubernetes safe release name from the branch
+    release_name_from_branch: bool,
+    // globs naming the environment variables templates may read
+    allowed_env: Vec<String>,
+    // the command line helm is given depends on its generation
+    helm_version: HelmVersion,
+    // --namespace, or Helm 3's -n on install
+    namespace: Option<String>,
 }
 
 impl HelmRuntime {
     pub(crate) fn new() -> HelmRuntime {
         HelmRuntime {
-            implicit_variables: HashMap::new(),
-            explicit_variables: HashMap::new(),
+            variables: Value::new_map(),
+            workspace: None,
+            strict: false,
+            unresolved: Vec::new(),
+            passthrough_args: Vec::new(),
+            prefix_output: false,
+            debug: false,
+            rendered_files: Vec::new(),
+            release_name_from_branch: false,
+            allowed_env: Vec::new(),
+            helm_version: HelmVersion::V2,
+            namespace: None,
         }
     }
 
-    pub(crate) fn set_implicit_var(&mut self, key: String, value: String) {
-        self.implicit_variables.insert(key, value);
+    pub(crate) fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
     }
 
-    pub(crate) fn set_explicit_var(&mut self, key: String, value: String) {
-        self.explicit_variables.insert(key, value);
+    pub(crate) fn set_prefix_output(&mut self, prefix_output: bool) {
+        self.prefix_output = prefix_output;
     }
 
-    /**
-    The idea here is that you can use any type as a lookup key, as long as that type could be “borrowed” from the stored key type.
-    http://idubrov.name/rust/2018/06/01/tricking-the-hashmap.html
-    **/
-    fn get_implicit_var<K: ?Sized>(&self, key: &K) -> Option<&String>
-    where
-        String: Borrow<K>,
-        K: Hash + Eq,
-    {
-        self.implicit_variables.get(key)
+    pub(crate) fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
     }
 
-    /**
-    The idea here is that you can use any type as a lookup key, as long as that type could be “borrowed” from the stored key type.
-    http://idubrov.name/rust/2018/06/01/tricking-the-hashmap.html
-    **/
-    fn get_explicit_var<K: ?Sized>(&self, key: &K) -> Option<&String>
-    where
-        String: Borrow<K>,
-        K: Hash + Eq,
-    {
-        self.explicit_variables.get(key)
+    pub(crate) fn debug(&self) -> bool {
+        self.debug
     }
 
-    fn read_values_file(&self, filename: &str) -> String {
-        let error_msg = format!("Something went wrong reading the file {}", filename);
-        let estr: &str = error_msg.as_str();
+    pub(crate) fn set_allowed_env(&mut self, patterns: &[String]) {
+        self.allowed_env = patterns.to_vec();
+    }
+
+    pub(crate) fn set_helm_version(&mut self, helm_version: HelmVersion) {
+        self.helm_version = helm_version;
+    }
 
-        fs::read_to_string(filename).expect(estr)
+    pub(crate) fn helm_version(&self) -> HelmVersion {
+        self.helm_version
+    }
+
+    pub(crate) fn set_namespace(&mut self, namespace: Option<&str>) {
+        self.namespace = namespace.map(|namespace| namespace.to_string());
     }
 
     /*
-    make the pattern that we are matching against
+    the namespace helm deploys to: --namespace, else $HELM_NAMESPACE for Helm 3, else the one
+    on the kube context
     */
-    fn make_regex_pattern<K: ?Sized>(&self, var: &K) -> String
-    where
-        String: Borrow<K>,
-        K: Display,
-    {
-        format!("(?i)\\{{\\{{\\s*{}\\s*\\}}\\}}", var)
+    pub(crate) fn release_namespace(&self) -> String {
+        if let Some(namespace) = &self.namespace {
+            return namespace.clone();
+        }
+        if self.helm_version == HelmVersion::V3 {
+            if let Some(namespace) = env::var("HELM_NAMESPACE").ok().filter(|ns| !ns.is_empty()) {
+                return namespace;
+            }
+        }
+        let forwarded = |flag: &str| {
+            self.passthrough_args
+                .iter()
+                .rev()
+                .find(|forwarded| forwarded.flag() == flag)
+                .and_then(|forwarded| forwarded.value())
+        };
+        kubeconfig::context_namespace(forwarded("--kubeconfig"), forwarded("--kube-context"))
     }
 
-    fn replace_implicit_vars(&self, result: &mut String) {
-        if let Ok(release_name_pattern) =
-            Regex::new(self.make_regex_pattern(".Release.Name").as_str())
-        {
-            if let Some(var) = self.get_implicit_var("release.name") {
-                *result = release_name_pattern
-                    .replace_all(result.as_str(), var.as_str())
-                    .into_owned();
-            }
+    pub(crate) fn set_release_name_from_branch(&mut self, release_name_from_branch: bool) {
+        self.release_name_from_branch = release_name_from_branch;
+    }
+
+    /*
+    the release name to give helm; with --release-name-from-branch the name is made DNS-1123 safe
+    and, when none was given, taken from the branch
+    */
+    pub(crate) fn release_name(&self, given: Option<&str>) -> Result<Option<String>, FoilError> {
+        if !self.release_name_from_branch {
+            return Ok(given.map(|name| name.to_string()));
         }
-        if let Ok(chart_name_pattern) = Regex::new(self.make_regex_pattern(".Chart.Name").as_str())
-        {
-            if let Some(var) = self.get_implicit_var("chart.name") {
-                *result = chart_name_pattern
-                    .replace_all(result.as_str(), var.as_str())
-                    .into_owned();
-            }
+
+        let name =
+            match given {
+                Some(name) => name.to_string(),
+                None => match self.get_var("Branch.Name") {
+                    Some(branch) => branch.to_string(),
+                    None => return Err(FoilError::Config(
+                        "--release-name-from-branch needs a branch name, none found in git or CI"
+                            .to_string(),
+                    )),
+                },
+            };
+        Ok(Some(k8sname::dns1123(&name, RELEASE_NAME_MAX)))
+    }
+
+    pub(crate) fn set_passthrough_args(&mut self, args: &[Forwarded]) {
+        self.passthrough_args = args.to_vec();
+    }
+
+    pub(crate) fn passthrough_args(&self) -> &[Forwarded] {
+        &self.passthrough_args
+    }
+
+    /*
+    forget the variables and rendered files of the last helm run but keep the settings, for
+    workflows that render the chart again for each helm call
+    */
+    pub(crate) fn reset(&mut self) {
+        self.variables = Value::new_map();
+        self.workspace = None;
+        self.unresolved.clear();
+        self.rendered_files.clear();
+    }
+
+    /*
+    set a template variable by its dotted path, "Release.Name" is {{ .Release.Name }}
+    */
+    pub(crate) fn set_var(&mut self, path: &str, value: Value) {
+        self.variables.set_path(path, value);
+    }
+
+    pub(crate) fn unset_var(&mut self, path: &str) {
+        self.variables.remove_path(path);
+    }
+
+    pub(crate) fn get_var(&self, path: &str) -> Option<&Value> {
+        self.variables.get_path(path)
+    }
+
+    /*
+    .Branch.Name, .Previous.Branch and .Git.* from the repository holding the chart; anything git
+    can't tell us stays unset so strict mode reports it
+    */
+    pub(crate) fn set_git_context(&mut self, git: &GitContext) {
+        if let Some(branch) = &git.branch {
+            self.set_var("Branch.Name", Value::from(branch.as_str()));
+        }
+        if let Some(previous_branch) = &git.previous_branch {
+            self.set_var("Previous.Branch", Value::from(previous_branch.as_str()));
+        }
+        if let Some(sha) = &git.sha {
+            self.set_var("Git.Sha", Value::from(sha.as_str()));
+        }
+        if let Some(short_sha) = git.short_sha() {
+            self.set_var("Git.ShortSha", Value::from(short_sha));
         }
+        if let Some(tag) = &git.tag {
+            self.set_var("Git.Tag", Value::from(tag.as_str()));
+        }
+        if let Some(dirty) = git.dirty {
+            self.set_var("Git.Dirty", Value::Bool(dirty));
+        }
+    }
 
-        if let Ok(branch_name_pattern) =
-            Regex::new(self.make_regex_pattern(".Branch.Name").as_str())
-        {
-            if let Some(var) = self.get_implicit_var("source.branch") {
-                *result = branch_name_pattern
-                    .replace_all(result.as_str(), var.as_str())
-                    .into_owned();
-            }
+    /*
+    read a values file, "-" reads it from stdin the same as helm does
+    */
+    fn read_values_file(&self, filename: &str) -> Result<String, FoilError> {
+        if filename == STDIN_FILENAME {
+            let mut contents = String::new();
+            io::stdin()
+                .read_to_string(&mut contents)
+                .map_err(|e| FoilError::io("<stdin>", e))?;
+            return Ok(contents);
         }
 
-        if let Ok(previous_branch_pattern) =
-            Regex::new(self.make_regex_pattern(".Previous.Branch").as_str())
-        {
-            if let Some(var) = self.get_implicit_var("previous.branch") {
-                *result = previous_branch_pattern
-                    .replace_all(result.as_str(), var.as_str())
-                    .into_owned();
+        fs::read_to_string(filename).map_err(|e| FoilError::io(filename, e))
+    }
+
+    /*
+    substitute every foil placeholder in one pass over the file
+    */
+    fn render_vars(&mut self, filename: &str, contents: &str) -> Result<String, FoilError> {
+        let rendered = render_template(filename, contents, &self.variables)?;
+        self.record_unresolved(filename, rendered.unresolved);
+        Ok(rendered.output)
+    }
+
+    fn record_unresolved(&mut self, filename: &str, unresolved: Vec<Unresolved>) {
+        for unresolved in unresolved {
+            if !self.strict {
+                eprintln!(
+                    "template variable {} is not set, left as is at {}:{}:{}",
+                    unresolved.reference, filename, unresolved.line, unresolved.column
+                );
             }
+            self.unresolved.push((filename.to_string(), unresolved));
         }
+    }
 
-        if let Ok(starting_canary_percentage_pattern) = Regex::new(
-            self.make_regex_pattern(".Starting.Canary.Percentage")
-                .as_str(),
-        ) {
-            if let Some(var) = self.get_implicit_var("starting.canary.percentage") {
-                *result = starting_canary_percentage_pattern
-                    .replace_all(result.as_str(), var.as_str())
-                    .into_owned();
+    /*
+    render the values.yaml of every subchart under charts/, nested ones included, with the same
+    variables plus .Subchart.Name and .Subchart.Path for the subchart being rendered
+    */
+    fn render_subchart_values(&mut self) -> Result<Vec<(String, PathBuf, String)>, FoilError> {
+        let chart_path = self.values_filename()?;
+        let chart_path = chart_path.trim_end_matches("/values.yaml").to_string();
+        let workspace = self.get_workspace()?;
+        let chart_dir = workspace.chart_dir().to_path_buf();
+        let subchart_dirs = workspace.subchart_dirs()?;
+
+        let mut rendered_files = Vec::new();
+        for subchart_dir in subchart_dirs {
+            let values_path = subchart_dir.join("values.yaml");
+            if !values_path.is_file() {
+                continue;
             }
+            let relative = subchart_dir
+                .strip_prefix(&chart_dir)
+                .unwrap_or(&subchart_dir);
+            let subchart_path = relative.to_string_lossy().into_owned();
+            let filename = format!("{}/{}/values.yaml", chart_path, subchart_path);
+
+            let subchart_name = match chartmeta::load(&subchart_dir)? {
+                Some(metadata) => metadata.get_path("Name").map(|name| name.to_string()),
+                None => None,
+            };
+            let subchart_name = subchart_name.unwrap_or_else(|| {
+                relative
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            });
+
+            let mut scope = self.variables.clone();
+            scope.set_path("Subchart.Name", Value::from(subchart_name));
+            scope.set_path("Subchart.Path", Value::from(subchart_path));
+
+            let contents =
+                fs::read_to_string(&values_path).map_err(|e| FoilError::io(&filename, e))?;
+            let rendered = render_template(&filename, &contents, &scope)?;
+            self.record_unresolved(&filename, rendered.unresolved);
+            rendered_files.push((filename, values_path, rendered.output));
         }
+        Ok(rendered_files)
     }
 
-    fn replace_explicit_vars(&self, override_file_result: &mut String, pattern_str: &str) {
-        if let Ok(pattern) = Regex::new(pattern_str) {
-            if let Some(var) = self.get_explicit_var(pattern_str) {
-                *override_file_result = pattern
-                    .replace_all(override_file_result.as_str(), var.as_str())
-                    .into_owned();
-            }
+    /*
+    in strict mode stop before helm runs if any placeholder was left in the rendered output
+    */
+    fn check_unresolved(&mut self) -> Result<(), FoilError> {
+        if !self.strict || self.unresolved.is_empty() {
+            return Ok(());
         }
+
+        Err(FoilError::Unresolved {
+            references: self.unresolved.clone(),
+            available: self.variables.leaf_paths(),
+        })
     }
 
     pub(crate) fn get_and_set_chart_name(
         &mut self,
         upgrade_command: &ArgMatches,
-        helm_command: &mut ProcessCommand,
-    ) {
-        if let Some(chart_path) = upgrade_command.value_of("CHART") {
-            helm_command.arg(chart_path);
-            let path = Path::new(chart_path);
-            // add global variable key/value 'chart.name'
-            if let Some(filename) = path.file_name() {
-                if let Some(filename_str) = filename.to_str() {
-                    self.set_implicit_var("chart.name".to_string(), filename_str.to_string());
+    ) -> Result<(), FoilError> {
+        match upgrade_command.value_of("CHART") {
+            Some(chart_path) => self.load_chart(chart_path),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn load_chart(&mut self, chart_path: &str) -> Result<(), FoilError> {
+        let path = Path::new(chart_path);
+        // helm gets a scratch copy of the chart, the rendered values.yaml is written there
+        let workspace = if workspace::is_chart_archive(path) {
+            Workspace::from_archive(path)?
+        } else if path.is_dir() {
+            Workspace::new(path)?
+        } else {
+            return Err(FoilError::Config(format!(
+                "chart {} is not a directory or a chart archive",
+                chart_path
+            )));
+        };
+
+        self.set_chart_vars(workspace.chart_dir(), chart_path)?;
+        self.set_git_context(&GitContext::discover(path));
+
+        self.workspace = Some(workspace);
+        Ok(())
+    }
+
+    /*
+    .Chart.* from Chart.yaml, only the directory name is known without one
+    */
+    pub(crate) fn set_chart_vars(
+        &mut self,
+        chart_dir: &Path,
+        chart_path: &str,
+    ) -> Result<(), FoilError> {
+        match chartmeta::load(chart_dir)? {
+            Some(metadata) => self.set_var("Chart", metadata),
+            None => {
+                // add global variable key/value 'Chart.Name'
+                if let Some(filename) = chart_dir.file_name() {
+                    if let Some(filename_str) = filename.to_str() {
+                        self.set_var("Chart.Name", Value::from(filename_str));
+                    }
                 }
-                // add global variable key/value 'chart.path'
-                self.set_implicit_var("chart.path".to_string(), chart_path.to_string());
             }
         }
+        // add global variable key/value 'Chart.Path'
+        self.set_var("Chart.Path", Value::from(chart_path));
+        Ok(())
+    }
+
+    /*
+    the variables of the foil run that started helm, when foil is running as its post-renderer;
+    false when there was none
+    */
+    pub(crate) fn load_context(&mut self) -> Result<bool, FoilError> {
+        let context = match env::var(CONTEXT_ENV) {
+            Ok(context) if !context.is_empty() => context,
+            _ => return Ok(false),
+        };
+        let context = serde_json::from_str::<serde_json::Value>(&context)
+            .map_err(|e| FoilError::Config(format!("error in {}: {}", CONTEXT_ENV, e)))?;
+        self.variables.merge(Value::from(context));
+        Ok(true)
+    }
+
+    /*
+    substitute the placeholders in the manifests helm rendered, in post-render mode
+    */
+    pub(crate) fn render_manifests(
+        &mut self,
+        global_args: &ArgMatches,
+        manifests: &str,
+    ) -> Result<String, FoilError> {
+        self.load_template_vars(global_args)?;
+        let rendered = Template::parse(manifests, Some(FOIL_ROOTS))
+            .and_then(|template| template.render(&self.variables))
+            .map_err(|error| FoilError::TemplateParse {
+                filename: "stdin".to_string(),
+                error,
+            })?;
+        self.record_unresolved("stdin", rendered.unresolved);
+        self.check_unresolved()?;
+        Ok(rendered.output)
     }
 
     pub(crate) fn apply_common_args(
@@ -161,133 +410,345 @@ impl HelmRuntime {
         global_args: &ArgMatches,
         subcommand: &ArgMatches,
         helm_command: &mut ProcessCommand,
-    ) {
-        let config_env_yaml: &mut String = &mut "".to_string();
-        let values_yaml: &mut String = &mut "".to_string();
-
-        match self.get_implicit_var("chart.path") {
-            Some(chart_path) => {
-                *values_yaml =
-                    self.read_values_file(format!("{}/values.yaml", chart_path).as_str());
-                // VALUES file
-                self.replace_implicit_vars(values_yaml);
-            }
-            None => {
-                panic!("[helm] missing chart specified on the command line");
-            }
-        }
+    ) -> Result<(), FoilError> {
+        self.apply_common_args_from(global_args, subcommand, "valueFiles", "set", helm_command)
+    }
 
-        let override_filename: &mut String = &mut "".to_string();
-        if let Some(override_file) = subcommand.value_of("valueFiles") {
-            *override_filename = override_file.to_string();
-            helm_command.args(&["-f", override_filename]);
-            *config_env_yaml = self.read_values_file(override_filename);
+    /*
+    the same with the -f files and --set values read from other arguments, for a workflow that
+    deploys a second chart with values of its own
+    */
+    pub(crate) fn apply_common_args_from(
+        &mut self,
+        global_args: &ArgMatches,
+        subcommand: &ArgMatches,
+        value_files_arg: &str,
+        set_arg: &str,
+        helm_command: &mut ProcessCommand,
+    ) -> Result<(), FoilError> {
+        self.load_template_vars(global_args)?;
+
+        // read from the workspace copy, which is also where a chart archive was unpacked
+        let values_filename = self.values_filename()?;
+        let values_path = self.get_workspace()?.chart_dir().join("values.yaml");
+        let values_yaml =
+            fs::read_to_string(values_path).map_err(|e| FoilError::io(&values_filename, e))?;
+
+        // every -f in command line order, helm gives the later files precedence
+        let mut override_files: Vec<(String, String)> = Vec::new();
+        if let Some(files) = subcommand.values_of(value_files_arg) {
+            for file in files {
+                override_files.push((file.to_string(), self.read_values_file(file)?));
+            }
         }
 
-        let set_values_collection: Vec<&str>;
-        if let Some(set_values) = subcommand.values_of("set") {
-            set_values_collection = set_values.collect();
+        if let Some(set_values) = subcommand.values_of(set_arg) {
             // loop over all --sets on the command line
-            for set_var in set_values_collection.iter() {
-                let split_parts: Vec<&str> = set_var.split('=').collect();
-
-                // convert the --set arguments on the command line to global variables formatted like
-                // {{.SetKey}}; example image.tag becomes {{.Values.image.tag}} template variable
-                let variable_format =
-                    self.make_regex_pattern(format!(".Values.{}", split_parts[0]).as_str());
-                println!("set template variable {}", variable_format);
-
-                self.set_explicit_var(variable_format.to_owned(), split_parts[1].to_owned());
-                self.set_implicit_var(split_parts[0].to_owned(), split_parts[1].to_owned());
-
-                // now replace the explicit variables declared from the command line from the -f override file
-                // env CONFIG/*.yaml files
-                self.replace_explicit_vars(config_env_yaml, &variable_format);
-
-                // now replace the explicit variables declared from the command line from the chart/values.yaml file
-                // VALUES file
-                self.replace_explicit_vars(values_yaml, &variable_format);
-
-                helm_command.args(&["--set", (*set_var).to_string().as_str()]);
-            }
-
-            // replace values.yaml contents
-            self.replace_implicit_vars(values_yaml);
+            for set_var in set_values {
+                // convert the --set arguments on the command line to template variables under .Values;
+                // example image.tag=v1 becomes the {{.Values.image.tag}} template variable
+                let values = self.variables.path_mut("Values");
+                let keys = strvals::parse_into(set_var, values).map_err(|e| {
+                    FoilError::Config(format!("failed parsing --set {}: {}", set_var, e))
+                })?;
+                if self.debug {
+                    for key in keys {
+                        eprintln!("set template variable .Values.{}", key);
+                    }
+                }
 
-            if subcommand.is_present("valueFiles") {
-                // replace global vars
-                // create more implicit variables in config/*.yaml
-                self.replace_implicit_vars(config_env_yaml);
+                // helm parses the argument itself, it gets it exactly as written
+                helm_command.args(["--set", set_var]);
             }
+        }
 
-            println!("{}", config_env_yaml); // => "xxxxx xxxxx!"
+        // VALUES file and the -f override files, each rendered in one pass
+        let values_yaml = self.render_vars(&values_filename, &values_yaml)?;
+        let subchart_files = self.render_subchart_values()?;
+        let mut rendered_files: Vec<(String, String)> = Vec::new();
+        for (filename, contents) in override_files {
+            let rendered = self.render_vars(&filename, &contents)?;
+            rendered_files.push((filename, rendered));
         }
+        self.check_unresolved()?;
 
         if let Some(tiller_namespace) = global_args.value_of("tiller-namespace") {
-            helm_command.args(&["--tiller-namespace", tiller_namespace]);
+            match self.helm_version {
+                HelmVersion::V2 => {
+                    helm_command.args(["--tiller-namespace", tiller_namespace]);
+                }
+                HelmVersion::V3 => {
+                    eprintln!("--tiller-namespace is a Helm 2 flag, not passed to Helm 3")
+                }
+            }
         }
-        if let Some(namespace) = global_args.value_of("namespace") {
-            helm_command.args(&["--namespace", namespace]);
+        if let Some(namespace) = &self.namespace {
+            helm_command.args(["--namespace", namespace]);
+        }
+        if subcommand.is_present("create-namespace") {
+            match self.helm_version {
+                // Helm 2 creates a missing namespace on its own
+                HelmVersion::V2 => {}
+                HelmVersion::V3 => {
+                    helm_command.arg("--create-namespace");
+                }
+            }
         }
         if let Some(timeout) = global_args.value_of("timeout") {
-            helm_command.args(&["--timeout", timeout]);
+            helm_command.args([
+                "--timeout",
+                &helmversion::timeout_arg(timeout, self.helm_version),
+            ]);
+        }
+        if let Some(output) = global_args.value_of("output") {
+            helm_command.args(["--output", output]);
         }
         if global_args.is_present("debug") {
             helm_command.arg("--debug");
         }
 
-        // write output
-        HelmRuntime::write_env_override_file(config_env_yaml, override_filename);
+        // write output into the scratch workspace, the source files are left untouched
+        // the chart's values come first, then its subcharts and the overrides, the same order
+        // helm applies them in
+        self.write_values_file(&values_yaml)?;
+        for (filename, rendered_path, rendered) in subchart_files {
+            self.write_rendered_file(filename, rendered_path, &rendered)?;
+        }
+        for (index, (filename, rendered)) in rendered_files.iter().enumerate() {
+            self.write_env_override_file(rendered, index, filename, helm_command)?;
+        }
 
-        self.write_values_file(values_yaml)
+        match self.helm_version {
+            HelmVersion::V2 => passthrough::insert_forwarded(helm_command, &self.passthrough_args),
+            HelmVersion::V3 => passthrough::insert_forwarded(
+                helm_command,
+                &helmversion::drop_helm2_flags(&self.passthrough_args),
+            ),
+        }
+        Ok(())
     }
 
-    fn write_values_file(&mut self, values_yaml: &mut String) {
-        if let Some(chart_path) = self.get_implicit_var("chart.path") {
-            match File::create(format!("{}/values.yaml", chart_path)) {
-                Ok(mut file) => {
-                    if let Err(err) = file.write_all(values_yaml.as_bytes()) {
-                        panic!("[helm] Error writing file {}", err);
+    /*
+    .Env from the allowed environment variables, then .Vars from each --vars-file in order and
+    each --var, later ones win; none of it goes to helm
+    */
+    fn load_template_vars(&mut self, global_args: &ArgMatches) -> Result<(), FoilError> {
+        // merged, so the .Env a post-renderer was handed isn't lost
+        self.variables
+            .path_mut("Env")
+            .merge(envvars::allowed_env(&self.allowed_env));
+
+        let vars = self.variables.path_mut("Vars");
+        if let Some(files) = global_args.values_of("vars-file") {
+            for file in files {
+                vars.merge(varsfile::load(file)?);
+            }
+        }
+        if let Some(assignments) = global_args.values_of("var") {
+            for assignment in assignments {
+                match assignment.split_once('=') {
+                    Some((key, value)) => vars.set_path(key, Value::from(value)),
+                    None => {
+                        return Err(FoilError::Config(format!(
+                            "--var {} is missing a value, expected key=value",
+                            assignment
+                        )))
                     }
                 }
-                Err(e) => {
-                    panic!("[helm] Error writing values file out {}", e);
-                }
             }
         }
+        Ok(())
+    }
+
+    fn values_filename(&self) -> Result<String, FoilError> {
+        match self.get_var("Chart.Path") {
+            Some(chart_path) => Ok(format!("{}/values.yaml", chart_path)),
+            None => Err(missing_chart()),
+        }
+    }
+
+    /*
+    the scratch copy of the chart, which is what helm is given
+    */
+    pub(crate) fn chart_dir(&self) -> Result<&Path, FoilError> {
+        Ok(self.get_workspace()?.chart_dir())
+    }
+
+    fn get_workspace(&self) -> Result<&Workspace, FoilError> {
+        self.workspace.as_ref().ok_or_else(missing_chart)
+    }
+
+    fn write_values_file(&mut self, values_yaml: &str) -> Result<(), FoilError> {
+        let rendered_path = self.get_workspace()?.chart_dir().join("values.yaml");
+        let values_filename = self.values_filename()?;
+        self.write_rendered_file(values_filename, rendered_path, values_yaml)
+    }
+
+    fn write_rendered_file(
+        &mut self,
+        source: String,
+        rendered_path: PathBuf,
+        rendered: &str,
+    ) -> Result<(), FoilError> {
+        self.get_workspace()?.write_file(&rendered_path, rendered)?;
+        self.rendered_files.push((source, rendered_path));
+        Ok(())
+    }
+
+    fn write_env_override_file(
+        &mut self,
+        config_env_yaml: &str,
+        index: usize,
+        override_filename: &str,
+        helm_command: &mut ProcessCommand,
+    ) -> Result<(), FoilError> {
+        let rendered_path = self
+            .get_workspace()?
+            .override_path(index, override_filename);
+        helm_command.arg("-f").arg(&rendered_path);
+        self.write_rendered_file(
+            override_filename.to_string(),
+            rendered_path,
+            config_env_yaml,
+        )
     }
 
-    fn write_env_override_file(config_env_yaml: &mut String, override_filename: &mut String) {
-        match File::create(override_filename) {
-            Ok(mut file) => {
-                if let Err(err) = file.write_all(config_env_yaml.as_bytes()) {
-                    panic!("[helm] Error writing file {}", err);
+    /*
+    copy every rendered file to `output_dir`, laid out as in the scratch workspace, or to stdout as
+    a stream of YAML documents each headed by the file it was rendered from
+    */
+    pub(crate) fn write_rendered(&self, output_dir: Option<&Path>) -> Result<(), FoilError> {
+        let workspace = self.get_workspace()?;
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+
+        for (source, rendered_path) in &self.rendered_files {
+            let contents =
+                fs::read_to_string(rendered_path).map_err(|e| FoilError::io(rendered_path, e))?;
+            match output_dir {
+                Some(output_dir) => {
+                    let target = output_dir.join(workspace.relative_path(rendered_path));
+                    workspace.write_file(&target, &contents)?;
+                }
+                None => {
+                    let newline = if contents.ends_with('\n') { "" } else { "\n" };
+                    write!(stdout, "---\n# Source: {}\n{}{}", source, contents, newline)
+                        .map_err(|e| FoilError::io("<stdout>", e))?;
                 }
-            }
-            Err(e) => {
-                panic!("[helm] Error writing override file {}", e);
             }
         }
+        Ok(())
     }
 
-    pub(crate) fn execute_helm(&self, helm_command: &mut ProcessCommand) {
-        println!("about to execute {:?}", helm_command);
-        let output: Output = helm_command
+    /*
+    run helm, streaming its output as it arrives; a non zero exit is reported as HelmFailed
+    */
+    pub(crate) fn execute_helm(&self, helm_command: &mut ProcessCommand) -> Result<(), FoilError> {
+        if self.debug {
+            eprintln!("about to execute {:?}", helm_command);
+        }
+        let binary = helm_command.get_program().to_string_lossy().into_owned();
+        let spawn_error = |source| FoilError::HelmSpawn { binary, source };
+        // for `--post-renderer helm_foil`, helm passes its environment on to the post-renderer
+        helm_command
+            .env(
+                CONTEXT_ENV,
+                serde_json::Value::from(&self.variables).to_string(),
+            )
+            .env(STRICT_ENV, self.strict.to_string());
+
+        if !self.prefix_output {
+            let status = helm_command
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit())
+                .status();
+            return match status {
+                Ok(status) => check_status(status),
+                Err(e) => Err(spawn_error(e)),
+            };
+        }
+
+        let mut child = match helm_command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .spawn()
-            .expect("[helm] failed to spawn helm")
-            .wait_with_output()
-            .expect("[helm] failed to wait on helm to complete");
-
-        if output.status.success() {
-            if let Ok(out) = String::from_utf8(output.stdout) {
-                println!("[helm] {}", out)
-            } else {
-                eprintln!("[helm] Error reading stdout");
+        {
+            Ok(child) => child,
+            Err(e) => return Err(spawn_error(e)),
+        };
+
+        let stdout = child
+            .stdout
+            .take()
+            .map(|out| thread::spawn(move || stream_lines(out, io::stdout())));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|err| thread::spawn(move || stream_lines(err, io::stderr())));
+        for stream in stdout.into_iter().chain(stderr) {
+            if let Ok(Err(e)) = stream.join() {
+                eprintln!("[helm] Error reading helm output {}", e);
             }
-        } else if let Ok(out) = String::from_utf8(output.stderr) {
-            eprintln!("[helm] {}", out);
-        } else {
-            eprintln!("[helm] Error reading stderr");
         }
+
+        match child.wait() {
+            Ok(status) => check_status(status),
+            Err(e) => Err(spawn_error(e)),
+        }
+    }
+}
+
+fn render_template(filename: &str, contents: &str, context: &Value) -> Result<Rendered, FoilError> {
+    Template::parse(contents, None)
+        .and_then(|template| template.render(context))
+        .map_err(|error| FoilError::TemplateParse {
+            filename: filename.to_string(),
+            error,
+        })
+}
+
+fn missing_chart() -> FoilError {
+    FoilError::Config("missing chart specified on the command line".to_string())
+}
+
+/*
+copy helm's output a line at a time with the [helm] prefix, the bytes are passed on as is
+*/
+fn stream_lines<R: Read, W: Write>(from: R, mut to: W) -> io::Result<()> {
+    let mut reader = BufReader::new(from);
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        to.write_all(b"[helm] ")?;
+        to.write_all(&line)?;
+        to.flush()?;
+        line.clear();
+    }
+    Ok(())
+}
+
+/*
+helm's exit code, or 128 + the signal number when helm was killed like a shell reports it
+*/
+fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    1
+}
+
+pub(crate) fn check_status(status: ExitStatus) -> Result<(), FoilError> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(FoilError::HelmFailed {
+            code: exit_code(status),
+        })
     }
 }
@@ -0,0 +1,127 @@
+use std::process::Command as ProcessCommand;
+
+use crate::foilerror::FoilError;
+use crate::passthrough::Forwarded;
+
+/*
+flags only Helm 2 understands; Helm 3 refuses to run with them
+*/
+const HELM2_ONLY_FLAGS: &[&str] = &[
+    "--home",
+    "--host",
+    "--tiller-connection-timeout",
+    "--tiller-namespace",
+    "--tls",
+    "--tls-ca-cert",
+    "--tls-cert",
+    "--tls-hostname",
+    "--tls-key",
+    "--tls-verify",
+];
+
+/**
+The generation of the helm binary foil drives. The two take different command lines: Helm 2
+names the release with `--name` and talks to tiller, Helm 3 takes the release as a positional
+argument and has no tiller.
+**/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HelmVersion {
+    V2,
+    V3,
+}
+
+impl HelmVersion {
+    /*
+    the major version out of `--helm-version 3`, `v3.5.0` or helm's own `Client: v2.16.1+g...`
+    */
+    pub(crate) fn parse(version: &str) -> Option<HelmVersion> {
+        let start = version
+            .find(|c: char| c.is_ascii_digit())
+            .filter(|start| *start == 0 || version[..*start].ends_with('v'))?;
+        let major: String = version[start..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        match major.parse::<u32>().ok()? {
+            2 => Some(HelmVersion::V2),
+            major if major >= 3 => Some(HelmVersion::V3),
+            _ => None,
+        }
+    }
+
+    /*
+    ask the binary once; --client keeps Helm 2 from trying to reach tiller
+    */
+    pub(crate) fn detect(helm_binary: &str) -> Result<HelmVersion, FoilError> {
+        let output = ProcessCommand::new(helm_binary)
+            .args(["version", "--short", "--client"])
+            .output()
+            .map_err(|source| FoilError::HelmSpawn {
+                binary: helm_binary.to_string(),
+                source,
+            })?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        stdout.lines().find_map(HelmVersion::parse).ok_or_else(|| {
+            FoilError::Config(format!(
+                "could not tell the helm version from `{} version --short`: {}, use --helm-version",
+                helm_binary,
+                stdout.trim()
+            ))
+        })
+    }
+}
+
+/*
+the forwarded arguments without the ones Helm 3 would reject, each dropped flag is reported
+*/
+pub(crate) fn drop_helm2_flags(forwarded: &[Forwarded]) -> Vec<Forwarded> {
+    forwarded
+        .iter()
+        .filter(|arg| {
+            let helm2_only = HELM2_ONLY_FLAGS.contains(&arg.flag());
+            if helm2_only {
+                eprintln!("{} is a Helm 2 flag, not passed to Helm 3", arg.flag());
+            }
+            !helm2_only
+        })
+        .cloned()
+        .collect()
+}
+
+/*
+Helm 2 takes the timeout in seconds, Helm 3 as a duration
+*/
+pub(crate) fn timeout_arg(timeout: &str, helm_version: HelmVersion) -> String {
+    if helm_version == HelmVersion::V3
+        && !timeout.is_empty()
+        && timeout.chars().all(|c| c.is_ascii_digit())
+    {
+        format!("{}s", timeout)
+    } else {
+        timeout.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!(HelmVersion::parse("2"), Some(HelmVersion::V2));
+        assert_eq!(HelmVersion::parse("v3.5.0+g32c2223"), Some(HelmVersion::V3));
+        assert_eq!(
+            HelmVersion::parse("Client: v2.16.1+gbbdfe5e"),
+            Some(HelmVersion::V2)
+        );
+        assert_eq!(HelmVersion::parse("1"), None);
+        assert_eq!(HelmVersion::parse("nonsense"), None);
+    }
+
+    #[test]
+    fn timeouts() {
+        assert_eq!(timeout_arg("300", HelmVersion::V2), "300");
+        assert_eq!(timeout_arg("300", HelmVersion::V3), "300s");
+        assert_eq!(timeout_arg("5m", HelmVersion::V3), "5m");
+    }
+}
@@ -1,7 +1,10 @@
-use std::process::{Command as ProcessCommand, Stdio};
+use std::process::Command as ProcessCommand;
 
 use crate::command::Command;
+use crate::foilerror::FoilError;
 use crate::helmruntime::HelmRuntime;
+use crate::helmversion::HelmVersion;
+use crate::templatevalue::Value;
 use clap::ArgMatches;
 
 pub(crate) struct InstallCommand<'a> {
@@ -9,7 +12,7 @@ pub(crate) struct InstallCommand<'a> {
 }
 
 impl<'a> InstallCommand<'a> {
-    pub(crate) fn new(execute_helm_command: &'a mut HelmRuntime) -> InstallCommand {
+    pub(crate) fn new(execute_helm_command: &'a mut HelmRuntime) -> InstallCommand<'a> {
         InstallCommand {
             helm_runtime: execute_helm_command,
         }
@@ -21,33 +24,90 @@ impl<'a> Command for InstallCommand<'a> {
         self.helm_runtime
     }
 
-    fn execute(&mut self, matches: &ArgMatches, commandline: &Option<&str>, helm_home_dir: String) {
+    fn execute(
+        &mut self,
+        matches: &ArgMatches,
+        commandline: &Option<&str>,
+        helm_binary: String,
+    ) -> Result<(), FoilError> {
         if let Some(command) = commandline {
             if let Some(install_command) = matches.subcommand_matches(command) {
-                let mut helm_command = ProcessCommand::new(format!("{}/helm", helm_home_dir));
-                helm_command
-                    .stderr(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .arg(command);
-
-                self.get_helm_runtime()
-                    .get_and_set_chart_name(install_command, &mut helm_command);
-
-                if let Some(release) = install_command.value_of("name") {
-                    helm_command.args(&["--name", release]);
-                    // add global variable key/value 'release.name'
-                    self.get_helm_runtime()
-                        .set_implicit_var("release.name".to_string(), release.to_string());
-                }
-
-                self.get_helm_runtime().apply_common_args(
+                // -n is Helm 2's --name and Helm 3's --namespace
+                let short_n = install_command.value_of("name-or-namespace");
+                let name = match self.get_helm_runtime().helm_version() {
+                    HelmVersion::V2 => install_command.value_of("name").or(short_n),
+                    HelmVersion::V3 => {
+                        if short_n.is_some() {
+                            self.get_helm_runtime().set_namespace(short_n);
+                        }
+                        install_command.value_of("name")
+                    }
+                };
+                // Helm 3's install NAME CHART
+                let release = if install_command.is_present("chart-after-name") {
+                    install_command.value_of("CHART")
+                } else {
+                    name
+                };
+
+                let mut helm_command = prepare_install(
+                    self.get_helm_runtime(),
                     matches,
                     install_command,
-                    &mut helm_command,
-                );
-
-                self.get_helm_runtime().execute_helm(&mut helm_command)
+                    &helm_binary,
+                    release,
+                )?;
+                return self.get_helm_runtime().execute_helm(&mut helm_command);
             }
         }
+        Ok(())
+    }
+}
+
+/*
+`helm install` for CHART with the values rendered, also used by the workflows that install;
+`release` is the name as given, none lets helm pick one
+*/
+pub(crate) fn prepare_install(
+    helm_runtime: &mut HelmRuntime,
+    matches: &ArgMatches,
+    install_command: &ArgMatches,
+    helm_binary: &str,
+    release: Option<&str>,
+) -> Result<ProcessCommand, FoilError> {
+    let mut helm_command = ProcessCommand::new(helm_binary);
+    helm_command.arg("install");
+
+    match install_command.value_of("chart-after-name") {
+        Some(chart_path) => helm_runtime.load_chart(chart_path)?,
+        None => helm_runtime.get_and_set_chart_name(install_command)?,
+    }
+
+    let release = helm_runtime.release_name(release)?;
+    let chart_dir = helm_runtime.chart_dir()?.to_path_buf();
+    match (&release, helm_runtime.helm_version()) {
+        // helm install [--name NAME] CHART
+        (Some(release), HelmVersion::V2) => {
+            helm_command.arg(&chart_dir).args(["--name", release]);
+        }
+        (None, HelmVersion::V2) => {
+            helm_command.arg(&chart_dir);
+        }
+        // helm install NAME CHART, Helm 3 insists on a name or --generate-name
+        (Some(release), HelmVersion::V3) => {
+            helm_command.arg(release).arg(&chart_dir);
+        }
+        (None, HelmVersion::V3) => {
+            helm_command.arg(&chart_dir).arg("--generate-name");
+        }
+    }
+    if let Some(release) = release {
+        // add global variable key/value 'Release.Name'
+        helm_runtime.set_var("Release.Name", Value::from(release.as_str()));
     }
+    let namespace = helm_runtime.release_namespace();
+    helm_runtime.set_var("Release.Namespace", Value::from(namespace));
+
+    helm_runtime.apply_common_args(matches, install_command, &mut helm_command)?;
+    Ok(helm_command)
 }
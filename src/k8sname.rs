@@ -0,0 +1,79 @@
+use sha2::{Digest, Sha256};
+
+// helm stores releases in secrets/configmaps whose names add a suffix, so releases stop at 53
+pub(crate) const RELEASE_NAME_MAX: usize = 53;
+// label values and most resource names
+pub(crate) const LABEL_MAX: usize = 63;
+
+const HASH_LEN: usize = 8;
+
+/**
+Turn any string, typically a branch name, into a DNS-1123 label that Kubernetes and helm accept:
+lower case, only `a-z`, `0-9` and `-`, no leading, trailing or repeated dashes. Names longer
+than `max_len` are cut short and end in a hash of the original so two long branch names that
+share a prefix still get different names, and the same branch always gets the same one.
+
+    feature/JIRA-123_New_Thing  ->  feature-jira-123-new-thing
+**/
+pub(crate) fn dns1123(name: &str, max_len: usize) -> String {
+    let mut safe = String::with_capacity(name.len());
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_lowercase() || c.is_ascii_digit() {
+            safe.push(c);
+        } else if !safe.is_empty() && !safe.ends_with('-') {
+            safe.push('-');
+        }
+    }
+    let safe = safe.trim_end_matches('-');
+
+    if !safe.is_empty() && safe.len() <= max_len {
+        return safe.to_string();
+    }
+
+    let hash = short_hash(name);
+    let keep = max_len.saturating_sub(HASH_LEN + 1);
+    let prefix = safe[..keep.min(safe.len())].trim_end_matches('-');
+    if prefix.is_empty() {
+        hash[..HASH_LEN.min(max_len)].to_string()
+    } else {
+        format!("{}-{}", prefix, hash)
+    }
+}
+
+fn short_hash(name: &str) -> String {
+    Sha256::digest(name.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>()[..HASH_LEN]
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn branch_names() {
+        assert_eq!(
+            dns1123("feature/JIRA-123_New_Thing", RELEASE_NAME_MAX),
+            "feature-jira-123-new-thing"
+        );
+        assert_eq!(dns1123("--Main--", LABEL_MAX), "main");
+    }
+
+    #[test]
+    fn long_names_are_cut_with_a_stable_hash() {
+        let a = format!("feature/{}-a", "x".repeat(80));
+        let b = format!("feature/{}-b", "x".repeat(80));
+        assert_eq!(dns1123(&a, RELEASE_NAME_MAX).len(), RELEASE_NAME_MAX);
+        assert_eq!(dns1123(&a, RELEASE_NAME_MAX), dns1123(&a, RELEASE_NAME_MAX));
+        assert_ne!(dns1123(&a, RELEASE_NAME_MAX), dns1123(&b, RELEASE_NAME_MAX));
+    }
+
+    #[test]
+    fn nothing_usable_left_is_a_hash() {
+        let name = dns1123("///", LABEL_MAX);
+        assert_eq!(name.len(), HASH_LEN);
+        assert!(name.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+}
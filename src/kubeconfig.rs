@@ -0,0 +1,109 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/*
+the namespace helm falls back to without --namespace: the one set on the kube context, which is
+--kube-context or the current context, from --kubeconfig, $KUBECONFIG or ~/.kube/config
+*/
+pub(crate) fn context_namespace(kubeconfig: Option<&str>, kube_context: Option<&str>) -> String {
+    let files = match kubeconfig {
+        Some(kubeconfig) => vec![PathBuf::from(kubeconfig)],
+        None => default_files(),
+    };
+    namespace_in(&files, kube_context).unwrap_or_else(|| "default".to_string())
+}
+
+fn default_files() -> Vec<PathBuf> {
+    match env::var_os("KUBECONFIG").filter(|paths| !paths.is_empty()) {
+        Some(paths) => env::split_paths(&paths).collect(),
+        None => env::var_os("HOME")
+            .map(|home| vec![Path::new(&home).join(".kube").join("config")])
+            .unwrap_or_default(),
+    }
+}
+
+/*
+kubectl's merge rules: the first file to set current-context wins, and so does the first file
+to define a context; files that are missing or don't parse are skipped
+*/
+fn namespace_in(files: &[PathBuf], kube_context: Option<&str>) -> Option<String> {
+    let configs: Vec<serde_yaml::Value> = files
+        .iter()
+        .filter_map(|file| fs::read_to_string(file).ok())
+        .filter_map(|contents| serde_yaml::from_str(&contents).ok())
+        .collect();
+
+    let context = match kube_context {
+        Some(context) => context.to_string(),
+        None => configs.iter().find_map(|config| {
+            config
+                .get("current-context")
+                .and_then(|context| context.as_str())
+                .filter(|context| !context.is_empty())
+                .map(|context| context.to_string())
+        })?,
+    };
+
+    configs
+        .iter()
+        .filter_map(|config| config.get("contexts")?.as_sequence())
+        .flatten()
+        .find(|entry| entry.get("name").and_then(|name| name.as_str()) == Some(context.as_str()))
+        .and_then(|entry| entry.get("context")?.get("namespace")?.as_str())
+        .filter(|namespace| !namespace.is_empty())
+        .map(|namespace| namespace.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+current-context: dev
+contexts:
+- name: dev
+  context:
+    cluster: local
+    namespace: team
+- name: prod
+  context:
+    cluster: remote
+";
+
+    fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn current_or_named_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = write(dir.path(), "config", CONFIG);
+        let files = [config.clone()];
+        assert_eq!(namespace_in(&files, None), Some("team".to_string()));
+        // a context without a namespace, or one that isn't defined
+        assert_eq!(namespace_in(&files, Some("prod")), None);
+        assert_eq!(namespace_in(&files, Some("other")), None);
+        assert_eq!(
+            context_namespace(config.to_str(), Some("prod")),
+            "default".to_string()
+        );
+    }
+
+    #[test]
+    fn first_file_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        let contexts = write(
+            dir.path(),
+            "contexts",
+            "contexts:\n- name: dev\n  context:\n    namespace: first\n",
+        );
+        let config = write(dir.path(), "config", CONFIG);
+        let missing = dir.path().join("missing");
+        let files = [missing, contexts, config];
+        // current-context only comes from the later file, the context from the earlier one
+        assert_eq!(namespace_in(&files, None), Some("first".to_string()));
+    }
+}
@@ -1,26 +1,174 @@
 use std::env;
+use std::io::{self, IsTerminal};
+use std::process;
 
 use clap::{
-    app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg, ArgMatches,
-    SubCommand,
+    app_from_crate, crate_authors, crate_description, crate_name, crate_version, App, AppSettings,
+    Arg, ArgMatches, ErrorKind, SubCommand,
 };
 
+use bluegreencommand::BlueGreenCommand;
+use canarycommand::CanaryCommand;
 use command::Command;
+use deploycommand::DeployCommand;
+use foilerror::FoilError;
+use helmbinary::HelmBinary;
 use helmruntime::HelmRuntime;
+use helmversion::HelmVersion;
 use installcommand::InstallCommand;
+use passthrough::{Forwarded, Passthrough};
+use postrendercommand::PostRenderCommand;
+use previewcommand::PreviewCommand;
+use rendercommand::RenderCommand;
 use upgradecommand::UpgradeCommand;
 
+mod bluegreencommand;
+mod canarycommand;
+mod chartmeta;
 mod command;
+mod deploycheck;
+mod deploycommand;
+mod duration;
+mod envvars;
+mod foilerror;
+mod gitcontext;
+mod helmbinary;
+mod helmclient;
 mod helmruntime;
+mod helmversion;
 mod installcommand;
+mod k8sname;
+mod kubeconfig;
+mod passthrough;
+mod postrendercommand;
+mod previewcommand;
+mod rendercommand;
+mod statefile;
+mod strvals;
+mod template;
+mod templatefuncs;
+mod templatevalue;
 mod upgradecommand;
+mod varsfile;
+mod workspace;
+
+// global flags only foil understands and whether they take a value, helm never sees them
+const FOIL_GLOBAL_FLAGS: &[(&str, bool)] = &[
+    ("--helm-binary", true),
+    ("--helm-version", true),
+    ("--prefix-output", false),
+    ("--strict", false),
+    ("--lenient", false),
+    ("--vars-file", true),
+    ("--var", true),
+    ("--allow-env", true),
+    ("--release-name-from-branch", false),
+];
+
+// the subcommands foil runs itself, anything else goes to helm with the original command line
+const FOIL_SUBCOMMANDS: &[&str] = &[
+    "install",
+    "upgrade",
+    "deploy",
+    "canary",
+    "bluegreen",
+    "preview",
+    "render",
+    "post-render",
+];
 
 #[derive(Debug, Clone, Default)]
-pub(crate) struct Main {}
+pub(crate) struct Main {
+    forwarded: Vec<Forwarded>,
+}
 
 impl Main {
-    pub(crate) fn parse_command_line<'a>(self: &Main) -> ArgMatches<'a> {
+    /*
+    parse the command line, any flag clap rejects is set aside to be forwarded to helm as is;
+    --help and --version are printed by clap, a command line it can't parse is a Usage error
+    */
+    pub(crate) fn parse_command_line<'a>(
+        self: &mut Main,
+        args: &[String],
+    ) -> Result<ArgMatches<'a>, FoilError> {
+        match Main::parse_forwarding(args.to_vec(), Passthrough::new()) {
+            Ok((matches, command_line, passthrough)) => {
+                self.forwarded = passthrough.forwarded(&command_line, matches.subcommand_name());
+                Ok(matches)
+            }
+            Err(e)
+                if e.kind == ErrorKind::HelpDisplayed || e.kind == ErrorKind::VersionDisplayed =>
+            {
+                e.exit()
+            }
+            Err(e) => Err(FoilError::Usage(
+                e.message
+                    .trim_start_matches("error: ")
+                    .trim_end()
+                    .to_string(),
+            )),
+        }
+    }
+
+    /*
+    an unknown flag is set aside on its own first; the argument after it goes with it as its
+    value only when clap then fails on that argument, or takes it for a subcommand helm would run
+    */
+    fn parse_forwarding<'a>(
+        command_line: Vec<String>,
+        passthrough: Passthrough,
+    ) -> Result<(ArgMatches<'a>, Vec<String>, Passthrough), clap::Error> {
+        let error = match Main::app().get_matches_from_safe(&command_line) {
+            Ok(matches) => return Ok((matches, command_line, passthrough)),
+            Err(e) => e,
+        };
+        let position = match error
+            .info
+            .as_ref()
+            .and_then(|info| info.first())
+            .filter(|_| error.kind == ErrorKind::UnknownArgument)
+            .and_then(|unknown| Passthrough::find(&command_line, unknown))
+        {
+            Some(position) => position,
+            None => return Err(error),
+        };
+
+        let (without_flag, taken) = passthrough.take(&command_line, position, false);
+        let alone = Main::parse_forwarding(without_flag, taken);
+        let value = match Passthrough::value_after(&command_line, position) {
+            Some(value) => value,
+            None => return alone,
+        };
+        match &alone {
+            Ok((matches, _, _)) => {
+                let subcommand = matches.subcommand_name();
+                if subcommand != Some(value) || FOIL_SUBCOMMANDS.contains(&value) {
+                    return alone;
+                }
+            }
+            Err(e)
+                if e.kind == ErrorKind::HelpDisplayed || e.kind == ErrorKind::VersionDisplayed =>
+            {
+                return alone
+            }
+            Err(_) => {}
+        }
+
+        let (without_value, taken) = passthrough.take(&command_line, position, true);
+        Main::parse_forwarding(without_value, taken).or(alone)
+    }
+
+    pub(crate) fn forwarded(self: &Main) -> &[Forwarded] {
+        &self.forwarded
+    }
+
+    fn app<'a, 'b>() -> App<'a, 'b> {
         app_from_crate!()
+            // subcommands foil doesn't handle are run by helm untouched
+            .setting(AppSettings::AllowExternalSubcommands)
+            .setting(AppSettings::VersionlessSubcommands)
+            // usage errors are reported like foil's own, also as JSON
+            .setting(AppSettings::ColorNever)
             // helm install subcommand
             .subcommand(
                 SubCommand::with_name("install")
@@ -29,27 +177,48 @@ impl Main {
                         Arg::with_name("CHART")
                             .required(true)
                             .takes_value(true)
-                            .help("directory location of the chart"),
+                            .value_name("NAME|CHART")
+                            .help("directory location of the chart, or the release name followed by the chart as in Helm 3"),
+                    )
+                    .arg(
+                        Arg::with_name("chart-after-name")
+                            .takes_value(true)
+                            .value_name("CHART")
+                            .help("directory location of the chart, when the release name comes first"),
                     )
                     .arg(
                         Arg::with_name("name")
                             .takes_value(true)
-                            .long("name")
-                            .short("n"),
+                            .long("name"),
+                    )
+                    .arg(
+                        Arg::with_name("name-or-namespace")
+                            .takes_value(true)
+                            .short("n")
+                            .help("the release name for Helm 2, the namespace for Helm 3, as each of them reads -n"),
                     )
                     .arg(
                         Arg::with_name("valueFiles")
                             .takes_value(true)
                             .multiple(true)
+                            .number_of_values(1)
+                            .use_delimiter(true)
                             .long("values")
-                            .short("f"),
+                            .short("f")
+                            .help("specify values in a YAML file, can be repeated or comma separated; - reads stdin"),
                     )
                     .arg(
                         Arg::with_name("set")
                             .multiple(true)
+                            .number_of_values(1)
                             .long("set")
                             .takes_value(true)
                             .help("set a variable override"),
+                    )
+                    .arg(
+                        Arg::with_name("create-namespace")
+                            .long("create-namespace")
+                            .help("create the release namespace if it is missing"),
                     ),
             )
             // helm upgrade subcommand
@@ -73,21 +242,378 @@ impl Main {
                         Arg::with_name("valueFiles")
                             .takes_value(true)
                             .multiple(true)
+                            .number_of_values(1)
+                            .use_delimiter(true)
+                            .long("values")
+                            .short("f")
+                            .help("specify values in a YAML file, can be repeated or comma separated; - reads stdin"),
+                    )
+                    .arg(
+                        Arg::with_name("force")
+                            .long("force")
+                            .help("Force the installation"),
+                    )
+                    .arg(
+                        Arg::with_name("create-namespace")
+                            .long("create-namespace")
+                            .help("create the release namespace if it is missing"),
+                    )
+                    .arg(
+                        Arg::with_name("rollback-on-failure")
+                            .long("rollback-on-failure")
+                            .help("roll back to the revision from before the upgrade when helm or --check fails"),
+                    )
+                    .arg(
+                        Arg::with_name("check")
+                            .long("check")
+                            .takes_value(true)
+                            .help("shell command run after the upgrade, a non-zero exit fails it"),
+                    )
+                    .arg(
+                        Arg::with_name("set")
+                            .multiple(true)
+                            .number_of_values(1)
+                            .long("set")
+                            .takes_value(true)
+                            .help("set a variable override"),
+                    ),
+            )
+            // render the values without calling helm
+            .subcommand(
+                SubCommand::with_name("render")
+                    .about("print the rendered values files without installing anything")
+                    .arg(
+                        Arg::with_name("CHART")
+                            .required(true)
+                            .takes_value(true)
+                            .help("directory location of the chart"),
+                    )
+                    .arg(
+                        Arg::with_name("name")
+                            .takes_value(true)
+                            .long("name")
+                            .short("n"),
+                    )
+                    .arg(
+                        Arg::with_name("valueFiles")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .use_delimiter(true)
+                            .long("values")
+                            .short("f")
+                            .help("specify values in a YAML file, can be repeated or comma separated; - reads stdin"),
+                    )
+                    .arg(
+                        Arg::with_name("set")
+                            .multiple(true)
+                            .number_of_values(1)
+                            .long("set")
+                            .takes_value(true)
+                            .help("set a variable override"),
+                    )
+                    .arg(
+                        Arg::with_name("output-dir")
+                            .long("output-dir")
+                            .takes_value(true)
+                            .help("write the rendered files to this directory instead of stdout"),
+                    ),
+            )
+            // install or upgrade, whichever the release needs
+            .subcommand(
+                SubCommand::with_name("deploy")
+                    .about("install the release if it doesn't exist yet, upgrade it if it does")
+                    .arg(
+                        Arg::with_name("RELEASE")
+                            .required(true)
+                            .takes_value(true)
+                            .help("name the deployment with this value"),
+                    )
+                    .arg(
+                        Arg::with_name("CHART")
+                            .required(true)
+                            .takes_value(true)
+                            .help("directory location of the chart"),
+                    )
+                    .arg(
+                        Arg::with_name("recover")
+                            .long("recover")
+                            .help("purge a Helm 2 release whose install failed or never finished, then install it again"),
+                    )
+                    .arg(
+                        Arg::with_name("valueFiles")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .use_delimiter(true)
+                            .long("values")
+                            .short("f")
+                            .help("specify values in a YAML file, can be repeated or comma separated; - reads stdin"),
+                    )
+                    .arg(
+                        Arg::with_name("force")
+                            .long("force")
+                            .help("Force the installation"),
+                    )
+                    .arg(
+                        Arg::with_name("create-namespace")
+                            .long("create-namespace")
+                            .help("create the release namespace if it is missing"),
+                    )
+                    .arg(
+                        Arg::with_name("set")
+                            .multiple(true)
+                            .number_of_values(1)
+                            .long("set")
+                            .takes_value(true)
+                            .help("set a variable override"),
+                    ),
+            )
+            // progressive rollout, one upgrade per step
+            .subcommand(
+                SubCommand::with_name("canary")
+                    .about("upgrade a release step by step with .Canary.Percentage, rolling back when a step fails")
+                    .arg(
+                        Arg::with_name("RELEASE")
+                            .required(true)
+                            .takes_value(true)
+                            .help("name the deployment with this value"),
+                    )
+                    .arg(
+                        Arg::with_name("CHART")
+                            .required(true)
+                            .takes_value(true)
+                            .help("directory location of the chart"),
+                    )
+                    .arg(
+                        Arg::with_name("steps")
+                            .long("steps")
+                            .required(true)
+                            .takes_value(true)
+                            .use_delimiter(true)
+                            .help("canary percentages in order, e.g. 5,25,50,100"),
+                    )
+                    .arg(
+                        Arg::with_name("interval")
+                            .long("interval")
+                            .takes_value(true)
+                            .default_value("5m")
+                            .help("how long to wait after a step before checking it and moving on"),
+                    )
+                    .arg(
+                        Arg::with_name("check")
+                            .long("check")
+                            .takes_value(true)
+                            .help("shell command run after each step, a non-zero exit rolls back"),
+                    )
+                    .arg(
+                        Arg::with_name("state-file")
+                            .long("state-file")
+                            .takes_value(true)
+                            .help("where progress is kept, .helm_foil/canary-RELEASE.json by default"),
+                    )
+                    .arg(
+                        Arg::with_name("resume")
+                            .long("resume")
+                            .help("carry on with an interrupted rollout from its state file"),
+                    )
+                    .arg(
+                        Arg::with_name("valueFiles")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .use_delimiter(true)
                             .long("values")
-                            .short("f"),
+                            .short("f")
+                            .help("specify values in a YAML file, can be repeated or comma separated; - reads stdin"),
                     )
                     .arg(
                         Arg::with_name("force")
                             .long("force")
                             .help("Force the installation"),
                     )
+                    .arg(
+                        Arg::with_name("create-namespace")
+                            .long("create-namespace")
+                            .help("create the release namespace if it is missing"),
+                    )
                     .arg(
                         Arg::with_name("set")
                             .multiple(true)
+                            .number_of_values(1)
                             .long("set")
                             .takes_value(true)
                             .help("set a variable override"),
                     ),
+            )
+            // deploy the branch as a new colour and switch the router to it
+            .subcommand(
+                SubCommand::with_name("bluegreen")
+                    .about("install the branch as its own release and switch the router release to it")
+                    .arg(
+                        Arg::with_name("RELEASE")
+                            .required(true)
+                            .takes_value(true)
+                            .help("base name, each colour is released as RELEASE-<branch>"),
+                    )
+                    .arg(
+                        Arg::with_name("CHART")
+                            .required(true)
+                            .takes_value(true)
+                            .help("directory location of the chart"),
+                    )
+                    .arg(
+                        Arg::with_name("router-chart")
+                            .long("router-chart")
+                            .required(true)
+                            .takes_value(true)
+                            .help("chart that routes traffic to the live colour"),
+                    )
+                    .arg(
+                        Arg::with_name("router-release")
+                            .long("router-release")
+                            .takes_value(true)
+                            .help("release name of the router, RELEASE by default"),
+                    )
+                    .arg(
+                        Arg::with_name("router-values")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .use_delimiter(true)
+                            .long("router-values")
+                            .help("values files for the router chart, can be repeated or comma separated"),
+                    )
+                    .arg(
+                        Arg::with_name("router-set")
+                            .multiple(true)
+                            .number_of_values(1)
+                            .long("router-set")
+                            .takes_value(true)
+                            .help("set a variable override on the router"),
+                    )
+                    .arg(
+                        Arg::with_name("delete-old")
+                            .long("delete-old")
+                            .help("delete the colour that was live once traffic has moved"),
+                    )
+                    .arg(
+                        Arg::with_name("grace-period")
+                            .long("grace-period")
+                            .takes_value(true)
+                            .default_value("0")
+                            .help("how long to wait before deleting the old colour, e.g. 10m"),
+                    )
+                    .arg(
+                        Arg::with_name("state-file")
+                            .long("state-file")
+                            .takes_value(true)
+                            .help("where the live colour is kept, .helm_foil/bluegreen-RELEASE.json by default"),
+                    )
+                    .arg(
+                        Arg::with_name("valueFiles")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .use_delimiter(true)
+                            .long("values")
+                            .short("f")
+                            .help("specify values in a YAML file, can be repeated or comma separated; - reads stdin"),
+                    )
+                    .arg(
+                        Arg::with_name("create-namespace")
+                            .long("create-namespace")
+                            .help("create the release namespace if it is missing"),
+                    )
+                    .arg(
+                        Arg::with_name("set")
+                            .multiple(true)
+                            .number_of_values(1)
+                            .long("set")
+                            .takes_value(true)
+                            .help("set a variable override"),
+                    ),
+            )
+            // a release per branch, and cleaning them up once the branch is gone
+            .subcommand(
+                SubCommand::with_name("preview")
+                    .about("deploy a preview release for the branch, or delete the ones whose branch is gone")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        SubCommand::with_name("up")
+                            .about("install or upgrade the branch's preview release, named after the branch")
+                            .arg(
+                                Arg::with_name("CHART")
+                                    .required(true)
+                                    .takes_value(true)
+                                    .help("directory location of the chart"),
+                            )
+                            .arg(
+                                Arg::with_name("prefix")
+                                    .long("prefix")
+                                    .takes_value(true)
+                                    .default_value("preview")
+                                    .help("put in front of the branch in the release and namespace names"),
+                            )
+                            .arg(
+                                Arg::with_name("valueFiles")
+                                    .takes_value(true)
+                                    .multiple(true)
+                                    .number_of_values(1)
+                                    .use_delimiter(true)
+                                    .long("values")
+                                    .short("f")
+                                    .help("specify values in a YAML file, can be repeated or comma separated; - reads stdin"),
+                            )
+                            .arg(
+                                Arg::with_name("set")
+                                    .multiple(true)
+                                    .number_of_values(1)
+                                    .long("set")
+                                    .takes_value(true)
+                                    .help("set a variable override"),
+                            )
+                            .arg(
+                                Arg::with_name("dry-run")
+                                    .long("dry-run")
+                                    .help("render and print the helm command without running it"),
+                            ),
+                    )
+                    .subcommand(
+                        SubCommand::with_name("gc")
+                            .about("delete the preview releases whose branch no longer exists")
+                            .arg(
+                                Arg::with_name("keep-branches")
+                                    .long("keep-branches")
+                                    .takes_value(true)
+                                    .default_value("git")
+                                    .help("git for the branches of the local repository, or a file with one branch per line"),
+                            )
+                            .arg(
+                                Arg::with_name("dry-run")
+                                    .long("dry-run")
+                                    .help("list the releases that would be deleted"),
+                            ),
+                    ),
+            )
+            // substitute placeholders in the manifests helm rendered
+            .subcommand(
+                SubCommand::with_name("post-render")
+                    .about("read rendered manifests on stdin and write them to stdout with the variables substituted, for helm --post-renderer")
+                    .arg(
+                        Arg::with_name("name")
+                            .takes_value(true)
+                            .long("name")
+                            .short("n")
+                            .env("HELM_FOIL_RELEASE_NAME"),
+                    )
+                    .arg(
+                        Arg::with_name("chart")
+                            .takes_value(true)
+                            .long("chart")
+                            .env("HELM_FOIL_CHART")
+                            .help("directory location of the chart, for the .Chart variables"),
+                    ),
             ) // now set global options
             .arg(
                 Arg::with_name("tiller-namespace")
@@ -96,6 +622,20 @@ impl Main {
                     .help("Specify the namespace to look for tiller")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("helm-binary")
+                    .long("helm-binary")
+                    .global(true)
+                    .takes_value(true)
+                    .help("Path of the helm binary to run, instead of HELM_BIN, the PATH or HELM_HOME"),
+            )
+            .arg(
+                Arg::with_name("helm-version")
+                    .long("helm-version")
+                    .global(true)
+                    .takes_value(true)
+                    .help("Helm major version, 2 or 3, instead of asking helm"),
+            )
             .arg(
                 Arg::with_name("namespace")
                     .long("namespace")
@@ -116,10 +656,92 @@ impl Main {
                     .global(true)
                     .help("Specify debug mode"),
             )
-            .get_matches()
+            .arg(
+                Arg::with_name("output")
+                    .long("output")
+                    .short("o")
+                    .global(true)
+                    .takes_value(true)
+                    .help("Output format, also passed to helm; json reports foil errors as JSON"),
+            )
+            .arg(
+                Arg::with_name("vars-file")
+                    .long("vars-file")
+                    .global(true)
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .env("HELM_FOIL_VARS_FILE")
+                    .help("Template variables for {{ .Vars.* }} from a YAML, JSON or .env file, not passed to helm"),
+            )
+            .arg(
+                Arg::with_name("var")
+                    .long("var")
+                    .global(true)
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Set a template variable {{ .Vars.key }} with key=value, not passed to helm"),
+            )
+            .arg(
+                Arg::with_name("allow-env")
+                    .long("allow-env")
+                    .global(true)
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .use_delimiter(true)
+                    .env("HELM_FOIL_ALLOW_ENV")
+                    .help("Environment variables templates may read with {{ .Env.NAME }}, comma separated globs like BUILD_*"),
+            )
+            .arg(
+                Arg::with_name("release-name-from-branch")
+                    .long("release-name-from-branch")
+                    .global(true)
+                    .help("Make the release name Kubernetes safe, taken from the branch when --name is not given"),
+            )
+            .arg(
+                Arg::with_name("prefix-output")
+                    .long("prefix-output")
+                    .global(true)
+                    .help("Prefix each line of helm's output with [helm]"),
+            )
+            .arg(
+                Arg::with_name("strict")
+                    .long("strict")
+                    .global(true)
+                    .conflicts_with("lenient")
+                    .help("Fail when a template variable is not set (default when CI is set, or set by HELM_FOIL_STRICT)"),
+            )
+            .arg(
+                Arg::with_name("lenient")
+                    .long("lenient")
+                    .global(true)
+                    .help("Leave template variables that are not set in the output as is"),
+            )
     }
+
     pub(crate) fn new() -> Main {
-        Main {}
+        Main {
+            forwarded: Vec::new(),
+        }
+    }
+
+    /*
+    strict unless --lenient is given; without either flag HELM_FOIL_STRICT decides, which is how
+    a post-renderer runs as strict as the foil that started helm, and otherwise strict is only
+    the default on CI
+    */
+    pub(crate) fn is_strict(self: &Main, matches: &ArgMatches) -> bool {
+        if matches.is_present("strict") {
+            true
+        } else if matches.is_present("lenient") {
+            false
+        } else if let Ok(strict) = env::var(helmruntime::STRICT_ENV) {
+            strict != "false" && strict != "0"
+        } else {
+            env::var_os("CI").is_some()
+        }
     }
 }
 
@@ -128,54 +750,191 @@ fn run_static_dispatch<T: Command>(
     command: &mut T,
     matches: &ArgMatches,
     commandline: &Option<&str>,
-    helm_home_dir: String,
-) {
-    command.echo("do this, static dispatch");
-    command.execute(matches, commandline, helm_home_dir);
+    helm_binary: String,
+) -> Result<(), FoilError> {
+    command.execute(matches, commandline, helm_binary)
 }
 
-fn run_dynamic_dispatch(
-    command: &mut dyn command::Command,
-    matches: &ArgMatches,
-    commandline: &Option<&str>,
-    helm_home_dir: String,
-) {
-    command.echo("do this dynamic dispatch");
-    command.execute(matches, commandline, helm_home_dir);
+fn helm_binary(matches: &ArgMatches) -> Result<String, FoilError> {
+    let helm_binary = HelmBinary::locate(matches.value_of("helm-binary"))?;
+    if matches.is_present("debug") {
+        eprintln!(
+            "using helm binary {} (from {})",
+            helm_binary.path, helm_binary.source
+        );
+    }
+    Ok(helm_binary.path)
 }
 
-fn main() {
-    let helm_home_dir;
-    if let Ok(homedir) = env::var("HELM_HOME") {
-        helm_home_dir = homedir;
-    } else {
-        panic!("[helm] Missing HELM_HOME environment variable");
+/*
+the command line without foil's own global flags and their values, which helm wouldn't understand
+*/
+fn without_foil_flags(args: &[String]) -> Vec<String> {
+    let mut kept = Vec::with_capacity(args.len());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let flag = arg.split('=').next().unwrap_or(arg);
+        match FOIL_GLOBAL_FLAGS.iter().find(|(name, _)| *name == flag) {
+            Some((_, takes_value)) => {
+                if *takes_value && !arg.contains('=') {
+                    args.next();
+                }
+            }
+            None => kept.push(arg.clone()),
+        }
     }
+    kept
+}
 
-    let main: Main = Main::new();
-    let matches: ArgMatches = main.parse_command_line();
+/*
+--helm-version when given, otherwise ask the helm binary
+*/
+fn helm_version(matches: &ArgMatches, helm_binary: &str) -> Result<HelmVersion, FoilError> {
+    match matches.value_of("helm-version") {
+        Some(version) => HelmVersion::parse(version)
+            .ok_or_else(|| FoilError::Config(format!("--helm-version {} is not 2 or 3", version))),
+        None => HelmVersion::detect(helm_binary),
+    }
+}
 
+/*
+--output json, for errors reported before clap could parse the command line
+*/
+fn wants_json(args: &[String]) -> bool {
+    args.windows(2)
+        .any(|pair| (pair[0] == "-o" || pair[0] == "--output") && pair[1] == "json")
+        || args
+            .iter()
+            .any(|arg| arg == "--output=json" || arg == "-ojson" || arg == "-o=json")
+}
+
+/*
+run the subcommand, everything it created is dropped before foil exits
+*/
+fn run(main: &Main, matches: &ArgMatches, args: &[String]) -> Result<(), FoilError> {
     let mut helm_runtime = HelmRuntime::new();
+    helm_runtime.set_strict(main.is_strict(matches));
+    helm_runtime.set_passthrough_args(main.forwarded());
+    helm_runtime.set_prefix_output(matches.is_present("prefix-output"));
+    helm_runtime.set_debug(matches.is_present("debug"));
+    helm_runtime.set_namespace(matches.value_of("namespace"));
+    let allowed_env: Vec<String> = matches
+        .values_of("allow-env")
+        .map(|patterns| patterns.map(|pattern| pattern.to_string()).collect())
+        .unwrap_or_default();
+    helm_runtime.set_allowed_env(&allowed_env);
+    helm_runtime.set_release_name_from_branch(matches.is_present("release-name-from-branch"));
     match matches.subcommand_name() {
         Some("install") => {
+            let helm_binary = helm_binary(matches)?;
+            helm_runtime.set_helm_version(helm_version(matches, &helm_binary)?);
             let mut command = InstallCommand::new(&mut helm_runtime);
             run_static_dispatch(
                 &mut command,
-                &matches,
+                matches,
                 &matches.subcommand_name(),
-                helm_home_dir,
-            );
+                helm_binary,
+            )
         }
         Some("upgrade") => {
+            let helm_binary = helm_binary(matches)?;
+            helm_runtime.set_helm_version(helm_version(matches, &helm_binary)?);
             let mut command = UpgradeCommand::new(&mut helm_runtime);
             run_static_dispatch(
                 &mut command,
-                &matches,
+                matches,
                 &matches.subcommand_name(),
-                helm_home_dir,
-            );
+                helm_binary,
+            )
         }
-        _ => panic!("[helm] Unknown command, only install/upgrade are currently supported"),
+        Some("deploy") => {
+            let helm_binary = helm_binary(matches)?;
+            helm_runtime.set_helm_version(helm_version(matches, &helm_binary)?);
+            let mut command = DeployCommand::new(&mut helm_runtime);
+            run_static_dispatch(
+                &mut command,
+                matches,
+                &matches.subcommand_name(),
+                helm_binary,
+            )
+        }
+        Some("canary") => {
+            let helm_binary = helm_binary(matches)?;
+            helm_runtime.set_helm_version(helm_version(matches, &helm_binary)?);
+            let mut command = CanaryCommand::new(&mut helm_runtime);
+            run_static_dispatch(
+                &mut command,
+                matches,
+                &matches.subcommand_name(),
+                helm_binary,
+            )
+        }
+        Some("bluegreen") => {
+            let helm_binary = helm_binary(matches)?;
+            helm_runtime.set_helm_version(helm_version(matches, &helm_binary)?);
+            let mut command = BlueGreenCommand::new(&mut helm_runtime);
+            run_static_dispatch(
+                &mut command,
+                matches,
+                &matches.subcommand_name(),
+                helm_binary,
+            )
+        }
+        Some("preview") => {
+            let helm_binary = helm_binary(matches)?;
+            helm_runtime.set_helm_version(helm_version(matches, &helm_binary)?);
+            let mut command = PreviewCommand::new(&mut helm_runtime);
+            run_static_dispatch(
+                &mut command,
+                matches,
+                &matches.subcommand_name(),
+                helm_binary,
+            )
+        }
+        Some("render") => {
+            // render never runs helm, so it doesn't need to find one
+            let mut command = RenderCommand::new(&mut helm_runtime);
+            run_static_dispatch(
+                &mut command,
+                matches,
+                &matches.subcommand_name(),
+                "helm".to_string(),
+            )
+        }
+        Some("post-render") => {
+            let mut command = PostRenderCommand::new(&mut helm_runtime);
+            run_static_dispatch(
+                &mut command,
+                matches,
+                &matches.subcommand_name(),
+                "helm".to_string(),
+            )
+        }
+        // list, status, rollback, ... go straight to helm with the original command line
+        _ => passthrough::exec_helm(&helm_binary(matches)?, &without_foil_flags(&args[1..])),
+    }
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    // helm runs `--post-renderer helm_foil` without arguments and the manifests on stdin, there
+    // is no way to tell it apart from a bare helm_foil with a pipe on stdin, which is documented
+    if args.len() == 1 && !io::stdin().is_terminal() {
+        args.push("post-render".to_string());
+    }
+    let mut main: Main = Main::new();
+    let matches: ArgMatches = match main.parse_command_line(&args) {
+        Ok(matches) => matches,
+        Err(e) => {
+            e.report(wants_json(&args));
+            process::exit(e.exit_code());
+        }
+    };
+
+    // the scratch workspace is already gone by the time we exit, process::exit skips destructors
+    if let Err(e) = run(&main, &matches, &args) {
+        e.report(matches.value_of("output") == Some("json"));
+        process::exit(e.exit_code());
     }
     //    match matches.subcommand_name() {
     //        Some("install") => {
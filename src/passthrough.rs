@@ -0,0 +1,254 @@
+use std::ffi::OsString;
+use std::process::Command as ProcessCommand;
+
+use crate::foilerror::FoilError;
+
+/*
+helm flags that never take a value, so the argument after one is never its value
+*/
+const HELM_BOOL_FLAGS: &[&str] = &[
+    "--atomic",
+    "--cleanup-on-fail",
+    "--create-namespace",
+    "--dep-up",
+    "--dependency-update",
+    "--devel",
+    "--disable-openapi-validation",
+    "--dry-run",
+    "--force",
+    "--generate-name",
+    "-g",
+    "--insecure-skip-tls-verify",
+    "--install",
+    "-i",
+    "--no-crd-hook",
+    "--no-hooks",
+    "--recreate-pods",
+    "--render-subchart-notes",
+    "--replace",
+    "--reset-values",
+    "--reuse-values",
+    "--skip-crds",
+    "--tls",
+    "--tls-verify",
+    "--verify",
+    "--wait",
+    "--wait-for-jobs",
+];
+
+/**
+An argument foil doesn't understand, with its value when it has one, and where it was on the
+command line counted from the subcommand.
+**/
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Forwarded {
+    pub(crate) position: usize,
+    pub(crate) args: Vec<String>,
+}
+
+impl Forwarded {
+    /*
+    the flag without an `=value`
+    */
+    pub(crate) fn flag(&self) -> &str {
+        let arg = self
+            .args
+            .first()
+            .map(|arg| arg.as_str())
+            .unwrap_or_default();
+        arg.split('=').next().unwrap_or(arg)
+    }
+
+    /*
+    the value, whether given as `--flag=value` or `--flag value`
+    */
+    pub(crate) fn value(&self) -> Option<&str> {
+        match self.args.first()?.split_once('=') {
+            Some((_, value)) => Some(value),
+            None => self.args.get(1).map(|arg| arg.as_str()),
+        }
+    }
+}
+
+/**
+Arguments foil doesn't understand, pulled out of the command line so clap can parse the rest.
+They are handed to helm verbatim, in the place they were given.
+**/
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Passthrough {
+    // positions in the original command line
+    forwarded: Vec<Forwarded>,
+    // every position taken out of the original command line, in order
+    taken: Vec<usize>,
+}
+
+impl Passthrough {
+    pub(crate) fn new() -> Passthrough {
+        Passthrough {
+            forwarded: Vec::new(),
+            taken: Vec::new(),
+        }
+    }
+
+    /*
+    where the unknown argument clap reported is; none when it can't be found, e.g. inside a
+    cluster of short flags, or isn't a flag
+    */
+    pub(crate) fn find(command_line: &[String], unknown: &str) -> Option<usize> {
+        if !unknown.starts_with('-') {
+            return None;
+        }
+        command_line
+            .iter()
+            .skip(1)
+            .position(|arg| arg == unknown || arg.starts_with(&format!("{}=", unknown)))
+            .map(|position| position + 1)
+    }
+
+    /*
+    the argument after the flag at `position` when it could be the flag's value
+    */
+    pub(crate) fn value_after(command_line: &[String], position: usize) -> Option<&str> {
+        let flag = command_line.get(position)?;
+        if flag.contains('=') || HELM_BOOL_FLAGS.contains(&flag.as_str()) {
+            return None;
+        }
+        command_line
+            .get(position + 1)
+            .filter(|next| !next.starts_with('-'))
+            .map(|next| next.as_str())
+    }
+
+    /*
+    the command line without the flag at `position`, and without the argument after it too when
+    `with_value`, and the passthrough that holds them
+    */
+    pub(crate) fn take(
+        &self,
+        command_line: &[String],
+        position: usize,
+        with_value: bool,
+    ) -> (Vec<String>, Passthrough) {
+        let count = if with_value { 2 } else { 1 };
+        let originals: Vec<usize> = (position..position + count)
+            .map(|position| self.original_position(position))
+            .collect();
+        let mut command_line = command_line.to_vec();
+        let args: Vec<String> = command_line.drain(position..position + count).collect();
+
+        let mut passthrough = self.clone();
+        for original in &originals {
+            let index = passthrough.taken.partition_point(|taken| taken < original);
+            passthrough.taken.insert(index, *original);
+        }
+        passthrough.forwarded.push(Forwarded {
+            position: originals[0],
+            args,
+        });
+        (command_line, passthrough)
+    }
+
+    /*
+    the forwarded arguments in command line order, with positions counted from the subcommand;
+    arguments given before it count as given right at it
+    */
+    pub(crate) fn forwarded(
+        &self,
+        command_line: &[String],
+        subcommand: Option<&str>,
+    ) -> Vec<Forwarded> {
+        let start = subcommand
+            .and_then(|subcommand| {
+                command_line
+                    .iter()
+                    .skip(1)
+                    .position(|arg| arg == subcommand)
+            })
+            .map(|position| self.original_position(position + 1))
+            .unwrap_or(0);
+        let mut forwarded: Vec<Forwarded> = self
+            .forwarded
+            .iter()
+            .map(|arg| Forwarded {
+                position: arg.position.saturating_sub(start),
+                args: arg.args.clone(),
+            })
+            .collect();
+        forwarded.sort_by_key(|arg| arg.position);
+        forwarded
+    }
+
+    /*
+    position in the original command line of what is now at `position`
+    */
+    fn original_position(&self, position: usize) -> usize {
+        let mut original = position;
+        for taken in &self.taken {
+            if *taken <= original {
+                original += 1;
+            }
+        }
+        original
+    }
+}
+
+/*
+put the forwarded arguments back into helm's command line where they were on foil's, never
+between one of foil's flags and its value; foil puts the positional arguments first, so a flag
+followed by something that isn't one is a flag with its value
+*/
+pub(crate) fn insert_forwarded(helm_command: &mut ProcessCommand, forwarded: &[Forwarded]) {
+    let mut args: Vec<OsString> = helm_command
+        .get_args()
+        .map(|arg| arg.to_os_string())
+        .collect();
+    let mut next_free = 0;
+    for arg in forwarded {
+        let mut position = arg.position.max(next_free).min(args.len());
+        while position > 0
+            && position < args.len()
+            && is_flag(&args[position - 1])
+            && !args[position - 1].to_string_lossy().contains('=')
+            && !is_flag(&args[position])
+        {
+            position += 1;
+        }
+        args.splice(position..position, arg.args.iter().map(OsString::from));
+        next_free = position + arg.args.len();
+    }
+
+    let mut rebuilt = ProcessCommand::new(helm_command.get_program());
+    rebuilt.args(args);
+    *helm_command = rebuilt;
+}
+
+fn is_flag(arg: &OsString) -> bool {
+    arg.to_string_lossy().starts_with('-')
+}
+
+/*
+run helm with the command line exactly as given, used for every subcommand foil doesn't handle
+*/
+pub(crate) fn exec_helm(helm_binary: &str, args: &[String]) -> Result<(), FoilError> {
+    let mut helm_command = ProcessCommand::new(helm_binary);
+    helm_command.args(args);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // exec only returns when helm could not be started
+        Err(FoilError::HelmSpawn {
+            binary: helm_binary.to_string(),
+            source: helm_command.exec(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    match helm_command.status() {
+        Ok(status) => crate::helmruntime::check_status(status),
+        Err(source) => Err(FoilError::HelmSpawn {
+            binary: helm_binary.to_string(),
+            source,
+        }),
+    }
+}
@@ -0,0 +1,77 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::command::Command;
+use crate::foilerror::FoilError;
+use crate::gitcontext::GitContext;
+use crate::helmruntime::HelmRuntime;
+use crate::templatevalue::Value;
+use clap::ArgMatches;
+
+/**
+Runs foil as a Helm 3 post-renderer: the manifests helm rendered come in on stdin and go back
+out on stdout with the placeholders substituted. helm gives a post-renderer no arguments, so
+the variables come from the foil run that started helm, or from flags and their environment
+variables such as `HELM_FOIL_RELEASE_NAME` and `HELM_FOIL_CHART`.
+**/
+pub(crate) struct PostRenderCommand<'a> {
+    helm_runtime: &'a mut HelmRuntime,
+}
+
+impl<'a> PostRenderCommand<'a> {
+    pub(crate) fn new(execute_helm_command: &'a mut HelmRuntime) -> PostRenderCommand<'a> {
+        PostRenderCommand {
+            helm_runtime: execute_helm_command,
+        }
+    }
+}
+
+impl<'a> Command for PostRenderCommand<'a> {
+    fn get_helm_runtime(&mut self) -> &mut HelmRuntime {
+        self.helm_runtime
+    }
+
+    fn execute(
+        &mut self,
+        matches: &ArgMatches,
+        commandline: &Option<&str>,
+        _helm_binary: String,
+    ) -> Result<(), FoilError> {
+        if let Some(command) = commandline {
+            if let Some(post_render_command) = matches.subcommand_matches(command) {
+                let from_foil = self.get_helm_runtime().load_context()?;
+
+                if let Some(chart_path) = post_render_command.value_of("chart") {
+                    let path = Path::new(chart_path);
+                    self.get_helm_runtime().set_chart_vars(path, chart_path)?;
+                    self.get_helm_runtime()
+                        .set_git_context(&GitContext::discover(path));
+                } else if !from_foil {
+                    self.get_helm_runtime()
+                        .set_git_context(&GitContext::discover(Path::new(".")));
+                }
+
+                let release = self
+                    .get_helm_runtime()
+                    .release_name(post_render_command.value_of("name"))?;
+                if let Some(release) = release {
+                    // add global variable key/value 'Release.Name'
+                    self.get_helm_runtime()
+                        .set_var("Release.Name", Value::from(release.as_str()));
+                }
+
+                let mut manifests = String::new();
+                io::stdin()
+                    .read_to_string(&mut manifests)
+                    .map_err(|e| FoilError::io("stdin", e))?;
+                let rendered = self
+                    .get_helm_runtime()
+                    .render_manifests(matches, &manifests)?;
+                return io::stdout()
+                    .write_all(rendered.as_bytes())
+                    .map_err(|e| FoilError::io("stdout", e));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::command::Command;
+use crate::foilerror::FoilError;
+use crate::gitcontext::{self, GitContext};
+use crate::helmclient::HelmClient;
+use crate::helmruntime::HelmRuntime;
+use crate::helmversion::HelmVersion;
+use crate::k8sname::{self, LABEL_MAX, RELEASE_NAME_MAX};
+use crate::statefile;
+use crate::templatevalue::Value;
+use crate::upgradecommand::Upgrade;
+use clap::ArgMatches;
+
+/**
+Throwaway environments per branch. `preview up` installs or upgrades the chart as a release
+named after the branch, in a namespace named after it too, and records it in the preview
+registry. `preview gc` deletes the recorded releases whose branch is gone. Both only print what
+they would do with `--dry-run`.
+**/
+pub(crate) struct PreviewCommand<'a> {
+    helm_runtime: &'a mut HelmRuntime,
+}
+
+impl<'a> PreviewCommand<'a> {
+    pub(crate) fn new(execute_helm_command: &'a mut HelmRuntime) -> PreviewCommand<'a> {
+        PreviewCommand {
+            helm_runtime: execute_helm_command,
+        }
+    }
+
+    fn up(
+        &mut self,
+        matches: &ArgMatches,
+        up_command: &ArgMatches,
+        helm_binary: &str,
+    ) -> Result<(), FoilError> {
+        let chart = up_command.value_of("CHART").unwrap_or_default();
+        let branch = GitContext::discover(Path::new(chart))
+            .branch
+            .ok_or_else(|| {
+                FoilError::Config(
+                    "preview needs the branch being deployed, none found in git or CI".to_string(),
+                )
+            })?;
+        let name = match up_command.value_of("prefix").unwrap_or_default() {
+            "" => branch.clone(),
+            prefix => format!("{}-{}", prefix, branch),
+        };
+        let release = k8sname::dns1123(&name, RELEASE_NAME_MAX);
+        let namespace = matches
+            .value_of("namespace")
+            .map(|namespace| namespace.to_string())
+            .unwrap_or_else(|| k8sname::dns1123(&name, LABEL_MAX));
+
+        let helm_runtime = self.get_helm_runtime();
+        let mut flags: Vec<&str> = Vec::new();
+        // a namespace given on the command line is added along with the other common args
+        if !matches.is_present("namespace") {
+            flags.extend(["--namespace", namespace.as_str()]);
+        }
+        if helm_runtime.helm_version() == HelmVersion::V3 {
+            flags.push("--create-namespace");
+        }
+        let vars = [
+            ("Release.Namespace", Some(Value::from(namespace.as_str()))),
+            ("Preview.Release", Some(Value::from(release.as_str()))),
+            ("Preview.Namespace", Some(Value::from(namespace.as_str()))),
+        ];
+        let mut helm_command = Upgrade {
+            install: true,
+            flags: &flags,
+            vars: &vars,
+            ..Upgrade::new(Some(&release), Some(chart))
+        }
+        .prepare(helm_runtime, matches, up_command, helm_binary)?;
+
+        if up_command.is_present("dry-run") {
+            eprintln!(
+                "dry run, {} would be deployed to {} with {:?}",
+                release, namespace, helm_command
+            );
+            return Ok(());
+        }
+        helm_runtime.execute_helm(&mut helm_command)?;
+
+        let registry_path = statefile::default_path("preview", "registry");
+        let mut registry = Registry::load(&registry_path)?;
+        registry.add(json!({
+            "release": release,
+            "namespace": namespace,
+            "branch": branch,
+            "chart": chart,
+        }));
+        registry.save(&registry_path)
+    }
+
+    fn gc(
+        &mut self,
+        matches: &ArgMatches,
+        gc_command: &ArgMatches,
+        helm_binary: &str,
+    ) -> Result<(), FoilError> {
+        let keep_branches = gc_command.value_of("keep-branches").unwrap_or("git");
+        let keep: BTreeSet<String> = if keep_branches == "git" {
+            gitcontext::branches(Path::new("."))
+                .ok_or_else(|| {
+                    FoilError::Config(
+                        "--keep-branches git needs a git repository in the working directory"
+                            .to_string(),
+                    )
+                })?
+                .into_iter()
+                .collect()
+        } else {
+            // one branch per line, blank lines and # comments are skipped
+            fs::read_to_string(keep_branches)
+                .map_err(|e| FoilError::io(keep_branches, e))?
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| line.to_string())
+                .collect()
+        };
+
+        let dry_run = gc_command.is_present("dry-run");
+        let client = HelmClient::new(helm_binary, self.get_helm_runtime(), matches);
+        let registry_path = statefile::default_path("preview", "registry");
+        let mut registry = Registry::load(&registry_path)?;
+        let (mut kept, mut deleted) = (0, 0);
+
+        for preview in registry.releases.clone() {
+            let field = |name: &str| preview[name].as_str().unwrap_or_default().to_string();
+            let (release, namespace, branch) =
+                (field("release"), field("namespace"), field("branch"));
+            if keep.contains(&branch) {
+                kept += 1;
+                continue;
+            }
+            let client = client.in_namespace(&namespace);
+            // deleted by hand already, it only has to leave the registry
+            let exists = !client.history(&release)?.is_empty();
+            if dry_run {
+                eprintln!(
+                    "dry run, would delete {} in {}, branch {} is gone{}",
+                    release,
+                    namespace,
+                    branch,
+                    if exists {
+                        ""
+                    } else {
+                        " (release already gone)"
+                    }
+                );
+            } else {
+                if exists {
+                    eprintln!(
+                        "deleting {} in {}, branch {} is gone",
+                        release, namespace, branch
+                    );
+                    client.delete(&release)?;
+                } else {
+                    eprintln!(
+                        "{} in {} is already gone, dropping it from the registry",
+                        release, namespace
+                    );
+                }
+                registry.remove(&release);
+                registry.save(&registry_path)?;
+            }
+            deleted += 1;
+        }
+        eprintln!(
+            "{} preview releases kept, {} {}",
+            kept,
+            deleted,
+            if dry_run { "to delete" } else { "deleted" }
+        );
+        Ok(())
+    }
+}
+
+impl<'a> Command for PreviewCommand<'a> {
+    fn get_helm_runtime(&mut self) -> &mut HelmRuntime {
+        self.helm_runtime
+    }
+
+    fn execute(
+        &mut self,
+        matches: &ArgMatches,
+        commandline: &Option<&str>,
+        helm_binary: String,
+    ) -> Result<(), FoilError> {
+        if let Some(command) = commandline {
+            if let Some(preview_command) = matches.subcommand_matches(command) {
+                return match preview_command.subcommand() {
+                    ("up", Some(up_command)) => self.up(matches, up_command, &helm_binary),
+                    ("gc", Some(gc_command)) => self.gc(matches, gc_command, &helm_binary),
+                    _ => Err(FoilError::Config(
+                        "preview needs a subcommand, up or gc".to_string(),
+                    )),
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+The preview releases foil has deployed and not yet deleted, one entry per release.
+**/
+struct Registry {
+    releases: Vec<serde_json::Value>,
+}
+
+impl Registry {
+    fn load(path: &Path) -> Result<Registry, FoilError> {
+        let releases = statefile::load(path)?
+            .and_then(|registry| registry["releases"].as_array().cloned())
+            .unwrap_or_default();
+        Ok(Registry { releases })
+    }
+
+    fn add(&mut self, preview: serde_json::Value) {
+        self.remove(preview["release"].as_str().unwrap_or_default());
+        self.releases.push(preview);
+    }
+
+    fn remove(&mut self, release: &str) {
+        self.releases
+            .retain(|preview| preview["release"].as_str() != Some(release));
+    }
+
+    fn save(&self, path: &Path) -> Result<(), FoilError> {
+        statefile::save(path, &json!({ "releases": self.releases }))
+    }
+}
@@ -0,0 +1,66 @@
+use std::path::Path;
+use std::process::Command as ProcessCommand;
+
+use crate::command::Command;
+use crate::foilerror::FoilError;
+use crate::helmruntime::HelmRuntime;
+use crate::templatevalue::Value;
+use clap::ArgMatches;
+
+/**
+Runs the same rendering as install and upgrade but prints the rendered values instead of
+calling helm, so nothing is deployed and the source files are left alone.
+**/
+pub(crate) struct RenderCommand<'a> {
+    helm_runtime: &'a mut HelmRuntime,
+}
+
+impl<'a> RenderCommand<'a> {
+    pub(crate) fn new(execute_helm_command: &'a mut HelmRuntime) -> RenderCommand<'a> {
+        RenderCommand {
+            helm_runtime: execute_helm_command,
+        }
+    }
+}
+
+impl<'a> Command for RenderCommand<'a> {
+    fn get_helm_runtime(&mut self) -> &mut HelmRuntime {
+        self.helm_runtime
+    }
+
+    fn execute(
+        &mut self,
+        matches: &ArgMatches,
+        commandline: &Option<&str>,
+        helm_binary: String,
+    ) -> Result<(), FoilError> {
+        if let Some(command) = commandline {
+            if let Some(render_command) = matches.subcommand_matches(command) {
+                // built the same way as for install so the rendering matches, but never run
+                let mut helm_command = ProcessCommand::new(helm_binary);
+
+                self.get_helm_runtime()
+                    .get_and_set_chart_name(render_command)?;
+
+                let release = self
+                    .get_helm_runtime()
+                    .release_name(render_command.value_of("name"))?;
+                if let Some(release) = release {
+                    // add global variable key/value 'Release.Name'
+                    self.get_helm_runtime()
+                        .set_var("Release.Name", Value::from(release.as_str()));
+                }
+
+                self.get_helm_runtime().apply_common_args(
+                    matches,
+                    render_command,
+                    &mut helm_command,
+                )?;
+
+                let output_dir = render_command.value_of("output-dir").map(Path::new);
+                return self.get_helm_runtime().write_rendered(output_dir);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::foilerror::FoilError;
+
+// workflow state is kept next to where foil runs, the way .git or .terraform are
+const STATE_DIR: &str = ".helm_foil";
+
+/**
+Small JSON files the multi-step workflows keep between runs, e.g. how far a canary rollout got
+or which colour is live, so the next run can carry on from there.
+**/
+pub(crate) fn default_path(workflow: &str, release: &str) -> PathBuf {
+    Path::new(STATE_DIR).join(format!("{}-{}.json", workflow, release))
+}
+
+/*
+`None` when there is no state yet
+*/
+pub(crate) fn load(path: &Path) -> Result<Option<serde_json::Value>, FoilError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(FoilError::io(path, e)),
+    };
+    serde_json::from_str(&contents)
+        .map(Some)
+        .map_err(|e| FoilError::Config(format!("error in state file {}: {}", path.display(), e)))
+}
+
+/*
+written to a temporary file first, so an interrupted run never leaves half a state file
+*/
+pub(crate) fn save(path: &Path, state: &serde_json::Value) -> Result<(), FoilError> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| FoilError::io(dir, e))?;
+    }
+    let partial = path.with_extension("json.partial");
+    let contents = serde_json::to_string_pretty(state).unwrap_or_default();
+    fs::write(&partial, contents + "\n").map_err(|e| FoilError::io(&partial, e))?;
+    fs::rename(&partial, path).map_err(|e| FoilError::io(path, e))
+}
@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+
+use crate::templatevalue::Value;
+
+// helm refuses list indexes above this to stop `a[99999999]=x` allocating a huge list
+const MAX_INDEX: usize = 65536;
+
+/**
+Parser for `--set` arguments following helm's strvals grammar, so foil sees the same values helm
+will:
+
+    name=value,other=value     several assignments, `\,` escapes a comma
+    outer.inner=value          nested maps
+    servers[0].port=80         list indexes, missing entries are filled with null
+    list={a,b,c}               list values
+
+Values are typed the way `--set` types them: true/false, null and integers are converted,
+everything else stays a string.
+**/
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+// the result of reading up to one of the stop characters; `stop` is None at the end of input
+struct Runes {
+    text: String,
+    stop: Option<char>,
+}
+
+enum ValueList {
+    List(Vec<Value>),
+    NotList,
+    End,
+}
+
+/*
+parse one --set argument into `target`, returning the keys it assigned
+*/
+pub(crate) fn parse_into(text: &str, target: &mut Value) -> Result<Vec<String>, String> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        pos: 0,
+    };
+    if !matches!(target, Value::Map(_)) {
+        *target = Value::new_map();
+    }
+
+    let mut keys = Vec::new();
+    while parser.pos < parser.chars.len() {
+        let start = parser.pos;
+        if let Value::Map(map) = target {
+            parser.key(map)?;
+        }
+        let assignment: String = parser.chars[start..parser.pos].iter().collect();
+        if let Some((key, _)) = assignment.split_once('=') {
+            keys.push(key.to_string());
+        }
+    }
+    Ok(keys)
+}
+
+impl Parser {
+    fn read(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).copied();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn unread(&mut self) {
+        self.pos -= 1;
+    }
+
+    /*
+    read up to and including the next unescaped stop character
+    */
+    fn runes_until(&mut self, stops: &[char]) -> Runes {
+        let mut text = String::new();
+        while let Some(c) = self.read() {
+            if c == '\\' {
+                if let Some(escaped) = self.read() {
+                    text.push(escaped);
+                }
+            } else if stops.contains(&c) {
+                return Runes {
+                    text,
+                    stop: Some(c),
+                };
+            } else {
+                text.push(c);
+            }
+        }
+        Runes { text, stop: None }
+    }
+
+    fn key(&mut self, data: &mut BTreeMap<String, Value>) -> Result<(), String> {
+        let runes = self.runes_until(&['=', '[', ',', '.']);
+        let key = runes.text;
+        match runes.stop {
+            None if key.is_empty() => Ok(()),
+            None => Err(format!("key \"{}\" has no value", key)),
+            Some('[') => {
+                let index = self.key_index()?;
+                let mut list = match data.remove(&key) {
+                    Some(Value::List(list)) => list,
+                    _ => Vec::new(),
+                };
+                let result = self.list_item(&mut list, index);
+                set(data, key, Value::List(list));
+                result
+            }
+            Some('=') => {
+                let value = match self.value_list()? {
+                    ValueList::List(list) => Value::List(list),
+                    ValueList::End => Value::from(""),
+                    ValueList::NotList => typed_value(&self.value()),
+                };
+                set(data, key, value);
+                Ok(())
+            }
+            Some(',') => Err(format!("key \"{}\" has no value (cannot end with ,)", key)),
+            _ => {
+                // '.', the key names a nested map
+                let mut inner = match data.remove(&key) {
+                    Some(Value::Map(inner)) => inner,
+                    _ => BTreeMap::new(),
+                };
+                let result = self.key(&mut inner);
+                if result.is_ok() && inner.is_empty() {
+                    return Err(format!("key map \"{}\" has no value", key));
+                }
+                if !inner.is_empty() {
+                    set(data, key, Value::Map(inner));
+                }
+                result
+            }
+        }
+    }
+
+    fn key_index(&mut self) -> Result<usize, String> {
+        let runes = self.runes_until(&[']']);
+        if runes.stop.is_none() {
+            return Err(format!("missing ] after index {}", runes.text));
+        }
+        match runes.text.parse::<i64>() {
+            Ok(index) if index < 0 => Err(format!("negative {} index not allowed", index)),
+            Ok(index) if index as usize > MAX_INDEX => Err(format!(
+                "index of {} is greater than maximum supported index value",
+                index
+            )),
+            Ok(index) => Ok(index as usize),
+            Err(_) => Err(format!("bad index \"{}\"", runes.text)),
+        }
+    }
+
+    /*
+    everything after `name[index]`: a value, another index or a nested key
+    */
+    fn list_item(&mut self, list: &mut Vec<Value>, index: usize) -> Result<(), String> {
+        let runes = self.runes_until(&['[', '.', '=']);
+        if !runes.text.is_empty() {
+            return Err(format!(
+                "unexpected data at end of array index: \"{}\"",
+                runes.text
+            ));
+        }
+        match runes.stop {
+            Some('=') => {
+                let value = match self.value_list()? {
+                    ValueList::List(values) => Value::List(values),
+                    ValueList::End => Value::from(""),
+                    ValueList::NotList => typed_value(&self.value()),
+                };
+                set_index(list, index, value);
+                Ok(())
+            }
+            Some('[') => {
+                let next_index = self.key_index()?;
+                let mut inner = match list.get_mut(index) {
+                    Some(Value::List(inner)) => std::mem::take(inner),
+                    _ => Vec::new(),
+                };
+                let result = self.list_item(&mut inner, next_index);
+                set_index(list, index, Value::List(inner));
+                result
+            }
+            Some('.') => {
+                let mut inner = match list.get_mut(index) {
+                    Some(Value::Map(inner)) => std::mem::take(inner),
+                    _ => BTreeMap::new(),
+                };
+                let result = self.key(&mut inner);
+                set_index(list, index, Value::Map(inner));
+                result
+            }
+            _ => Err("parse error: unexpected end of input after index".to_string()),
+        }
+    }
+
+    /*
+    a `{a,b}` list value; anything else is left for `value` to read
+    */
+    fn value_list(&mut self) -> Result<ValueList, String> {
+        match self.read() {
+            None => return Ok(ValueList::End),
+            Some('{') => {}
+            Some(_) => {
+                self.unread();
+                return Ok(ValueList::NotList);
+            }
+        }
+
+        let mut list = Vec::new();
+        loop {
+            let runes = self.runes_until(&[',', '}']);
+            match runes.stop {
+                None => return Err("list must terminate with '}'".to_string()),
+                Some('}') => {
+                    // a ',' after the list separates it from the next assignment
+                    if let Some(c) = self.read() {
+                        if c != ',' {
+                            self.unread();
+                        }
+                    }
+                    if !(runes.text.is_empty() && list.is_empty()) {
+                        list.push(typed_value(&runes.text));
+                    }
+                    return Ok(ValueList::List(list));
+                }
+                _ => list.push(typed_value(&runes.text)),
+            }
+        }
+    }
+
+    fn value(&mut self) -> String {
+        self.runes_until(&[',']).text
+    }
+}
+
+fn set(data: &mut BTreeMap<String, Value>, key: String, value: Value) {
+    if !key.is_empty() {
+        data.insert(key, value);
+    }
+}
+
+fn set_index(list: &mut Vec<Value>, index: usize, value: Value) {
+    if list.len() <= index {
+        list.resize(index + 1, Value::Null);
+    }
+    list[index] = value;
+}
+
+/*
+the types --set gives a value, --set-string would keep them all as strings
+*/
+fn typed_value(text: &str) -> Value {
+    if text.eq_ignore_ascii_case("true") {
+        Value::Bool(true)
+    } else if text.eq_ignore_ascii_case("false") {
+        Value::Bool(false)
+    } else if text.eq_ignore_ascii_case("null") {
+        Value::Null
+    } else if text == "0" {
+        Value::Int(0)
+    } else if !text.starts_with('0') {
+        // like helm, a leading zero keeps the value a string so 0755 isn't read as 755
+        text.parse::<i64>()
+            .map(Value::Int)
+            .unwrap_or_else(|_| Value::from(text))
+    } else {
+        Value::from(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Value {
+        let mut values = Value::new_map();
+        parse_into(text, &mut values).unwrap();
+        values
+    }
+
+    #[test]
+    fn types_values_like_set() {
+        let values = parse("a=1,b=two,c=true,d=null,e=0755");
+        assert_eq!(values.get_path("a"), Some(&Value::Int(1)));
+        assert_eq!(values.get_path("b"), Some(&Value::from("two")));
+        assert_eq!(values.get_path("c"), Some(&Value::Bool(true)));
+        assert_eq!(values.get_path("d"), Some(&Value::Null));
+        assert_eq!(values.get_path("e"), Some(&Value::from("0755")));
+    }
+
+    #[test]
+    fn nested_maps_and_indexes() {
+        let values = parse("image.tag=v1,servers[1].port=80");
+        assert_eq!(values.get_path("image.tag"), Some(&Value::from("v1")));
+        assert_eq!(values.get_path("servers.0"), Some(&Value::Null));
+        assert_eq!(values.get_path("servers.1.port"), Some(&Value::Int(80)));
+    }
+
+    #[test]
+    fn lists_and_escaped_commas() {
+        let values = parse(r"hosts={a,b},note=x\,y");
+        assert_eq!(
+            values.get_path("hosts"),
+            Some(&Value::List(vec![Value::from("a"), Value::from("b")]))
+        );
+        assert_eq!(values.get_path("note"), Some(&Value::from("x,y")));
+    }
+
+    #[test]
+    fn rejects_missing_value() {
+        let mut values = Value::new_map();
+        assert!(parse_into("name", &mut values).is_err());
+    }
+}
@@ -0,0 +1,676 @@
+use std::fmt;
+
+use crate::templatefuncs;
+use crate::templatevalue::Value;
+
+/**
+Parsed form of a values file. Text outside `{{ ... }}` is kept as is; each foil action is parsed
+into an expression tree up front so a file is rendered in a single pass.
+
+An action is treated as a foil expression when it starts with a field path (`.Release.Name`),
+a literal, a parenthesised pipeline or a known function, and uses no function or variable foil
+doesn't have. Anything else (`{{ include ... }}`, `{{- if }}`, `{{ $var }}`,
+`{{ .Values.a | toYaml }}`) is copied through untouched so values meant for helm's own `tpl`
+survive rendering. `{{-` and `-}}` trim the whitespace before and after a foil action the same
+as Go templates do, once it resolves.
+**/
+#[derive(Debug, Clone)]
+pub(crate) struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Action(Action),
+}
+
+#[derive(Debug, Clone)]
+struct Action {
+    expr: Expr,
+    source: String,
+    line: usize,
+    column: usize,
+    // {{- and -}}
+    trim_left: bool,
+    trim_right: bool,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Expr {
+    Field(Vec<String>),
+    Literal(Value),
+    Call(String, Vec<Expr>),
+    // every stage after the first is a Call that receives the previous result as its last argument
+    Pipeline(Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TemplateError {
+    pub(crate) message: String,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/**
+A reference to a variable that is not in the variable tree, reported with the position of the
+action it appears in.
+**/
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Unresolved {
+    pub(crate) reference: String,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Rendered {
+    pub(crate) output: String,
+    pub(crate) unresolved: Vec<Unresolved>,
+}
+
+enum EvalError {
+    Unresolved(String),
+    Function(String),
+}
+
+impl Template {
+    /*
+    `roots` is for text that is full of other templates' actions, such as manifests: only
+    actions whose fields all start at one of them are foil's
+    */
+    pub(crate) fn parse(text: &str, roots: Option<&[&str]>) -> Result<Template, TemplateError> {
+        let mut nodes = Vec::new();
+        let mut rest = text;
+        // line and column of the start of `rest`, tracked as we go so large files stay linear
+        let mut cursor = (1, 1);
+
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                nodes.push(Node::Text(rest[..start].to_string()));
+            }
+            advance(&mut cursor, &rest[..start]);
+            let (line, column) = cursor;
+            let body_start = start + 2;
+            let end = find_action_end(&rest[body_start..]).map(|end| body_start + end);
+            let body = match end {
+                Some(end) => &rest[body_start..end],
+                None => &rest[body_start..],
+            };
+            let (body, trim_left, trim_right) = trim_markers(body);
+
+            if !is_foil_expression(body, roots) {
+                // not ours, copy the opening braces and keep scanning after them
+                nodes.push(Node::Text("{{".to_string()));
+                advance(&mut cursor, "{{");
+                rest = &rest[body_start..];
+                continue;
+            }
+
+            let end = match end {
+                Some(end) => end,
+                None => {
+                    return Err(TemplateError {
+                        message: "unclosed action".to_string(),
+                        line,
+                        column,
+                    })
+                }
+            };
+
+            let expr = Parser::new(body)
+                .and_then(|mut parser| parser.parse_action())
+                .map_err(|message| TemplateError {
+                    message,
+                    line,
+                    column,
+                })?;
+
+            nodes.push(Node::Action(Action {
+                expr,
+                source: rest[start..end + 2].to_string(),
+                line,
+                column,
+                trim_left,
+                trim_right,
+            }));
+            advance(&mut cursor, &rest[start..end + 2]);
+            rest = &rest[end + 2..];
+        }
+        if !rest.is_empty() {
+            nodes.push(Node::Text(rest.to_string()));
+        }
+
+        Ok(Template { nodes })
+    }
+
+    /*
+    render against the variable tree; unresolved actions are left in the output exactly as written
+    */
+    pub(crate) fn render(&self, context: &Value) -> Result<Rendered, TemplateError> {
+        let mut output = String::new();
+        let mut unresolved = Vec::new();
+        // where the text right before the current action starts in the output, for {{-
+        let mut text_start = None;
+        // a resolved action ended with -}}, the whitespace after it goes
+        let mut trim_next = false;
+
+        for node in &self.nodes {
+            match node {
+                Node::Text(text) => {
+                    let text = if trim_next { text.trim_start() } else { text };
+                    trim_next = trim_next && text.is_empty();
+                    text_start.get_or_insert(output.len());
+                    output.push_str(text);
+                }
+                Node::Action(action) => match eval(&action.expr, context) {
+                    Ok(value) => {
+                        if let (true, Some(start)) = (action.trim_left, text_start) {
+                            let kept = output[start..].trim_end().len();
+                            output.truncate(start + kept);
+                        }
+                        output.push_str(&value.to_string());
+                        text_start = None;
+                        trim_next = action.trim_right;
+                    }
+                    Err(EvalError::Unresolved(reference)) => {
+                        // left exactly as written, trim markers and the whitespace around them
+                        output.push_str(&action.source);
+                        text_start = None;
+                        trim_next = false;
+                        unresolved.push(Unresolved {
+                            reference,
+                            line: action.line,
+                            column: action.column,
+                        });
+                    }
+                    Err(EvalError::Function(message)) => {
+                        return Err(TemplateError {
+                            message,
+                            line: action.line,
+                            column: action.column,
+                        })
+                    }
+                },
+            }
+        }
+
+        Ok(Rendered { output, unresolved })
+    }
+}
+
+fn eval(expr: &Expr, context: &Value) -> Result<Value, EvalError> {
+    match expr {
+        Expr::Field(path) => context
+            .lookup(path)
+            .cloned()
+            .ok_or_else(|| EvalError::Unresolved(format!(".{}", path.join(".")))),
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Call(name, args) => call(name, args, None, context),
+        Expr::Pipeline(stages) => {
+            let mut piped = None;
+            for stage in stages {
+                piped = Some(match (stage, piped) {
+                    (Expr::Call(name, args), Some(previous)) => {
+                        call(name, args, Some(previous), context)
+                    }
+                    (stage, _) => eval(stage, context),
+                });
+            }
+            piped.unwrap_or(Ok(Value::Null))
+        }
+    }
+}
+
+fn call(
+    name: &str,
+    args: &[Expr],
+    piped: Option<Result<Value, EvalError>>,
+    context: &Value,
+) -> Result<Value, EvalError> {
+    let mut values = Vec::with_capacity(args.len() + 1);
+    let evaluated = args.iter().map(|arg| eval(arg, context)).chain(piped);
+    for value in evaluated {
+        match value {
+            Ok(value) => values.push(value),
+            Err(EvalError::Unresolved(_)) if templatefuncs::accepts_missing(name) => {
+                values.push(Value::Null)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    // env reads the allowed environment under .Env, a name that isn't there is unresolved
+    if name == "env" {
+        return match values.as_slice() {
+            [variable] => {
+                let variable = variable.to_string();
+                context
+                    .lookup(&["Env", variable.as_str()])
+                    .cloned()
+                    .ok_or_else(|| EvalError::Unresolved(format!("env {:?}", variable)))
+            }
+            _ => Err(EvalError::Function(format!(
+                "wrong number of args for env: want 1 got {}",
+                values.len()
+            ))),
+        };
+    }
+    templatefuncs::call(name, &values).map_err(EvalError::Function)
+}
+
+/*
+move a 1-based (line, column) cursor past some text
+*/
+fn advance(cursor: &mut (usize, usize), text: &str) {
+    for c in text.chars() {
+        if c == '\n' {
+            *cursor = (cursor.0 + 1, 1);
+        } else {
+            cursor.1 += 1;
+        }
+    }
+}
+
+/*
+byte offset of the closing braces, skipping over any inside string literals
+*/
+fn find_action_end(body: &str) -> Option<usize> {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in body.char_indices() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' && q == '"' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None => {
+                if c == '"' || c == '`' {
+                    quote = Some(c);
+                } else if body[i..].starts_with("}}") {
+                    return Some(i);
+                }
+            }
+        }
+    }
+    None
+}
+
+/*
+the action body without its `-` trim markers, which need whitespace next to them as in Go so
+`{{-3}}` stays the number -3
+*/
+fn trim_markers(body: &str) -> (&str, bool, bool) {
+    let trim_left = body.starts_with('-') && body[1..].starts_with(|c: char| c.is_whitespace());
+    let body = if trim_left { &body[1..] } else { body };
+    let trim_right =
+        body.ends_with('-') && body[..body.len() - 1].ends_with(|c: char| c.is_whitespace());
+    let body = if trim_right {
+        &body[..body.len() - 1]
+    } else {
+        body
+    };
+    (body, trim_left, trim_right)
+}
+
+fn is_foil_expression(body: &str, roots: Option<&[&str]>) -> bool {
+    let trimmed = body.trim_start();
+    let mut chars = trimmed.chars();
+    let starts_like_foil = match chars.next() {
+        Some('.') => chars.next().is_some_and(is_ident_start),
+        Some('"') | Some('`') | Some('(') => true,
+        Some(c) if c.is_ascii_digit() => true,
+        Some(c) if is_ident_start(c) => {
+            let ident: String = trimmed.chars().take_while(|c| is_ident_char(*c)).collect();
+            templatefuncs::is_function(&ident)
+        }
+        _ => false,
+    };
+    starts_like_foil && !is_foreign(body, roots)
+}
+
+/*
+an action that calls a function or reads a variable foil doesn't have, or a field outside
+`roots`, belongs to helm's `tpl` or to a chart template; one that doesn't tokenize is left for
+the parser to report
+*/
+fn is_foreign(body: &str, roots: Option<&[&str]>) -> bool {
+    match tokenize(body) {
+        Ok(tokens) => tokens.iter().any(|token| match token {
+            Token::Ident(ident) => {
+                !matches!(ident.as_str(), "true" | "false" | "nil")
+                    && !templatefuncs::is_function(ident)
+            }
+            Token::Variable(_) => true,
+            Token::Field(path) => roots.is_some_and(|roots| {
+                !path
+                    .first()
+                    .is_some_and(|root| roots.contains(&root.as_str()))
+            }),
+            _ => false,
+        }),
+        Err(_) => false,
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Field(Vec<String>),
+    Ident(String),
+    Variable(String),
+    Str(String),
+    Number(Value),
+    Pipe,
+    LParen,
+    RParen,
+}
+
+fn tokenize(body: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = body.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '|' {
+            tokens.push(Token::Pipe);
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else if c == '.' {
+            let mut path = Vec::new();
+            while i < chars.len() && chars[i] == '.' {
+                i += 1;
+                let start = i;
+                while i < chars.len() && is_ident_char(chars[i]) {
+                    i += 1;
+                }
+                if start == i {
+                    return Err("bad field path".to_string());
+                }
+                path.push(chars[start..i].iter().collect());
+            }
+            tokens.push(Token::Field(path));
+        } else if c == '"' {
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err("unterminated quoted string".to_string()),
+                    Some('"') => break,
+                    Some('\\') => {
+                        i += 1;
+                        match chars.get(i) {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some(other) => s.push(*other),
+                            None => return Err("unterminated quoted string".to_string()),
+                        }
+                    }
+                    Some(other) => s.push(*other),
+                }
+                i += 1;
+            }
+            tokens.push(Token::Str(s));
+            i += 1;
+        } else if c == '`' {
+            let start = i + 1;
+            let end = chars[start..]
+                .iter()
+                .position(|c| *c == '`')
+                .ok_or_else(|| "unterminated raw quoted string".to_string())?;
+            tokens.push(Token::Str(chars[start..start + end].iter().collect()));
+            i = start + end + 1;
+        } else if c.is_ascii_digit()
+            || (c == '-' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()))
+        {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let number = match text.parse::<i64>() {
+                Ok(n) => Value::Int(n),
+                Err(_) => Value::Float(
+                    text.parse::<f64>()
+                        .map_err(|_| format!("bad number syntax: {}", text))?,
+                ),
+            };
+            tokens.push(Token::Number(number));
+        } else if is_ident_start(c) {
+            let start = i;
+            while i < chars.len() && is_ident_char(chars[i]) {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c == '$' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && is_ident_char(chars[i]) {
+                i += 1;
+            }
+            tokens.push(Token::Variable(chars[start..i].iter().collect()));
+        } else {
+            return Err(format!("unexpected \"{}\" in action", c));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/*
+recursive descent over the action tokens:
+    pipeline := command ('|' command)*
+    command  := function operand* | operand
+    operand  := field | string | number | true | false | nil | '(' pipeline ')'
+*/
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(body: &str) -> Result<Parser, String> {
+        Ok(Parser {
+            tokens: tokenize(body)?,
+            pos: 0,
+        })
+    }
+
+    fn parse_action(&mut self) -> Result<Expr, String> {
+        let expr = self.parse_pipeline()?;
+        match self.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {:?} in action", token)),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Expr, String> {
+        let mut stages = vec![self.parse_command()?];
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            let stage = self.parse_command()?;
+            if let Expr::Call(_, _) = stage {
+                stages.push(stage);
+            } else {
+                return Err("non executable command in pipeline stage".to_string());
+            }
+        }
+        if stages.len() == 1 {
+            Ok(stages.remove(0))
+        } else {
+            Ok(Expr::Pipeline(stages))
+        }
+    }
+
+    fn parse_command(&mut self) -> Result<Expr, String> {
+        if let Some(Token::Ident(name)) = self.peek() {
+            if templatefuncs::is_function(name) {
+                let name = name.clone();
+                self.next();
+                let mut args = Vec::new();
+                while let Some(token) = self.peek() {
+                    if *token == Token::Pipe || *token == Token::RParen {
+                        break;
+                    }
+                    args.push(self.parse_operand()?);
+                }
+                return Ok(Expr::Call(name, args));
+            }
+        }
+        let operand = self.parse_operand()?;
+        match self.peek() {
+            None | Some(Token::Pipe) | Some(Token::RParen) => Ok(operand),
+            Some(_) => Err("can't give argument to non-function".to_string()),
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Field(path)) => Ok(Expr::Field(path)),
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Token::Number(n)) => Ok(Expr::Literal(n)),
+            Some(Token::Ident(ident)) => match ident.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "nil" => Ok(Expr::Literal(Value::Null)),
+                _ if templatefuncs::is_function(&ident) => Ok(Expr::Call(ident, Vec::new())),
+                _ => Err(format!("function \"{}\" not defined", ident)),
+            },
+            Some(Token::LParen) => {
+                let expr = self.parse_pipeline()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err("unclosed left paren".to_string()),
+                }
+            }
+            Some(Token::Variable(name)) => Err(format!("undefined variable \"{}\"", name)),
+            Some(token) => Err(format!("unexpected {:?} in operand", token)),
+            None => Err("missing value for command".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> Value {
+        let mut context = Value::new_map();
+        context.set_path("Release.Name", Value::from("web"));
+        context.set_path("Branch.Name", Value::from("feature/x"));
+        context
+    }
+
+    fn render(text: &str) -> Rendered {
+        Template::parse(text, None)
+            .unwrap()
+            .render(&context())
+            .unwrap()
+    }
+
+    #[test]
+    fn renders_fields_and_pipelines() {
+        let rendered = render("name: {{ .Release.Name }}-{{ .Branch.Name | kebab | upper }}");
+        assert_eq!(rendered.output, "name: web-FEATURE-X");
+        assert!(rendered.unresolved.is_empty());
+    }
+
+    #[test]
+    fn leaves_unresolved_as_written() {
+        let rendered = render("a: 1\nb: {{ .Git.Sha }}\n");
+        assert_eq!(rendered.output, "a: 1\nb: {{ .Git.Sha }}\n");
+        assert_eq!(
+            rendered.unresolved,
+            vec![Unresolved {
+                reference: ".Git.Sha".to_string(),
+                line: 2,
+                column: 4,
+            }]
+        );
+    }
+
+    #[test]
+    fn default_fills_in_missing() {
+        let rendered = render(r#"{{ .Git.Tag | default "latest" }}"#);
+        assert_eq!(rendered.output, "latest");
+        assert!(rendered.unresolved.is_empty());
+    }
+
+    #[test]
+    fn copies_foreign_actions() {
+        for text in [
+            r#"{{ include "x" . }}"#,
+            "{{- if .Values.a }}",
+            "{{ $var }}",
+            "{{ .Values.a | toYaml }}",
+            "{{ .Values.b | default $v }}",
+        ] {
+            let rendered = render(text);
+            assert_eq!(rendered.output, text);
+            assert!(rendered.unresolved.is_empty(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn trims_around_resolved_actions() {
+        assert_eq!(render("a:  {{- .Release.Name }}").output, "a:web");
+        assert_eq!(render("{{ .Release.Name -}}  \n b").output, "webb");
+        let rendered = render("a: {{- .Git.Sha }}");
+        assert_eq!(rendered.output, "a: {{- .Git.Sha }}");
+        assert_eq!(rendered.unresolved.len(), 1);
+    }
+
+    #[test]
+    fn owned_roots_only() {
+        let template = Template::parse(
+            "{{ .Labels.instance }} {{ .Release.Name }}",
+            Some(&["Release"]),
+        );
+        let rendered = template.unwrap().render(&context()).unwrap();
+        assert_eq!(rendered.output, "{{ .Labels.instance }} web");
+        assert!(rendered.unresolved.is_empty());
+    }
+
+    #[test]
+    fn reports_parse_errors_with_position() {
+        let error = Template::parse("a: 1\nb: {{ .Release.Name", None).unwrap_err();
+        assert_eq!((error.line, error.column), (2, 4));
+        assert_eq!(error.message, "unclosed action");
+    }
+}
@@ -0,0 +1,275 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use inflector::Inflector;
+use sha2::{Digest, Sha256};
+
+use crate::k8sname::{self, LABEL_MAX};
+use crate::templatevalue::Value;
+
+/*
+names of every function that can be called inside a foil placeholder; they follow their Sprig
+namesakes so anyone who writes Helm templates already knows them
+*/
+const FUNCTIONS: &[&str] = &[
+    "default",
+    "quote",
+    "squote",
+    "upper",
+    "lower",
+    "title",
+    "kebab",
+    "kebabcase",
+    "snake",
+    "snakecase",
+    "camel",
+    "camelcase",
+    "trunc",
+    "trimSuffix",
+    "replace",
+    "sha256sum",
+    "b64enc",
+    "b64dec",
+    "dns1123",
+    // evaluated by the template itself, it needs the .Env variables
+    "env",
+];
+
+pub(crate) fn is_function(name: &str) -> bool {
+    FUNCTIONS.contains(&name)
+}
+
+/*
+functions that still run when an argument refers to a variable that is not set,
+the missing argument is passed in as Null
+*/
+pub(crate) fn accepts_missing(name: &str) -> bool {
+    name == "default"
+}
+
+/**
+Call a template function. Piped values arrive as the last argument, the same as Go templates,
+so `{{ .Branch.Name | default "master" }}` calls `default("master", branch)`.
+**/
+pub(crate) fn call(name: &str, args: &[Value]) -> Result<Value, String> {
+    match name {
+        "default" => {
+            let (fallback, given) = two_args(name, args)?;
+            if given.is_empty() {
+                Ok(fallback.clone())
+            } else {
+                Ok(given.clone())
+            }
+        }
+        // nil arguments are dropped like Sprig does
+        "quote" => Ok(Value::String(
+            args.iter()
+                .filter(|arg| **arg != Value::Null)
+                .map(|arg| go_quote(&arg.to_string()))
+                .collect::<Vec<String>>()
+                .join(" "),
+        )),
+        "squote" => Ok(Value::String(
+            args.iter()
+                .filter(|arg| **arg != Value::Null)
+                .map(|arg| format!("'{}'", arg))
+                .collect::<Vec<String>>()
+                .join(" "),
+        )),
+        "upper" => Ok(Value::String(
+            one_arg(name, args)?.to_string().to_uppercase(),
+        )),
+        "lower" => Ok(Value::String(
+            one_arg(name, args)?.to_string().to_lowercase(),
+        )),
+        // strings.Title: upper case the first letter of every word, leave the rest alone
+        "title" => Ok(Value::String(title(&one_arg(name, args)?.to_string()))),
+        "kebab" | "kebabcase" => Ok(Value::String(
+            one_arg(name, args)?.to_string().to_kebab_case(),
+        )),
+        "snake" | "snakecase" => Ok(Value::String(
+            one_arg(name, args)?.to_string().to_snake_case(),
+        )),
+        // Sprig's camelcase capitalises the first word too: http_server is HttpServer
+        "camel" | "camelcase" => Ok(Value::String(
+            one_arg(name, args)?.to_string().to_pascal_case(),
+        )),
+        "trunc" => {
+            let (length, given) = two_args(name, args)?;
+            Ok(Value::String(trunc(length, &given.to_string())?))
+        }
+        "trimSuffix" => {
+            let (suffix, given) = two_args(name, args)?;
+            let (suffix, given) = (suffix.to_string(), given.to_string());
+            Ok(Value::String(
+                given.strip_suffix(&suffix).unwrap_or(&given).to_string(),
+            ))
+        }
+        "replace" => match args {
+            [old, new, given] => Ok(Value::String(
+                given
+                    .to_string()
+                    .replace(&old.to_string(), &new.to_string()),
+            )),
+            _ => Err(wrong_arg_count(name, 3, args.len())),
+        },
+        "sha256sum" => {
+            let digest = Sha256::digest(one_arg(name, args)?.to_string().as_bytes());
+            Ok(Value::String(
+                digest.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            ))
+        }
+        "b64enc" => Ok(Value::String(
+            BASE64.encode(one_arg(name, args)?.to_string()),
+        )),
+        "b64dec" => {
+            let decoded = BASE64
+                .decode(one_arg(name, args)?.to_string())
+                .map_err(|e| format!("b64dec: {}", e))?;
+            Ok(Value::String(
+                String::from_utf8_lossy(&decoded).into_owned(),
+            ))
+        }
+        // a Kubernetes safe name, at most 63 characters unless a length is given
+        "dns1123" => match args {
+            [given] => Ok(Value::String(k8sname::dns1123(
+                &given.to_string(),
+                LABEL_MAX,
+            ))),
+            [Value::Int(length), given] if *length > 0 => Ok(Value::String(k8sname::dns1123(
+                &given.to_string(),
+                *length as usize,
+            ))),
+            [length, _] => Err(format!(
+                "dns1123: length must be a positive integer, got {}",
+                length
+            )),
+            _ => Err(wrong_arg_count(name, 1, args.len())),
+        },
+        _ => Err(format!("function \"{}\" not defined", name)),
+    }
+}
+
+/*
+Go's %q, which Sprig's quote uses: a double quoted string with Go escapes, so the output is the
+same as helm's own quote
+*/
+fn go_quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\u{7}' => quoted.push_str("\\a"),
+            '\u{8}' => quoted.push_str("\\b"),
+            '\u{c}' => quoted.push_str("\\f"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\u{b}' => quoted.push_str("\\v"),
+            c if c < ' ' || c == '\u{7f}' => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn title(text: &str) -> String {
+    let mut titled = String::with_capacity(text.len());
+    let mut word_start = true;
+    for c in text.chars() {
+        if word_start {
+            titled.extend(c.to_uppercase());
+        } else {
+            titled.push(c);
+        }
+        word_start = c.is_whitespace();
+    }
+    titled
+}
+
+/*
+the first `length` characters, or the last ones when `length` is negative
+*/
+fn trunc(length: &Value, text: &str) -> Result<String, String> {
+    let length = match length {
+        Value::Int(length) => *length,
+        other => return Err(format!("trunc: length must be an integer, got {}", other)),
+    };
+    let count = text.chars().count() as i64;
+    if length < 0 && count + length > 0 {
+        Ok(text.chars().skip((count + length) as usize).collect())
+    } else if length >= 0 && count > length {
+        Ok(text.chars().take(length as usize).collect())
+    } else {
+        Ok(text.to_string())
+    }
+}
+
+fn one_arg<'a>(name: &str, args: &'a [Value]) -> Result<&'a Value, String> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err(wrong_arg_count(name, 1, args.len())),
+    }
+}
+
+fn two_args<'a>(name: &str, args: &'a [Value]) -> Result<(&'a Value, &'a Value), String> {
+    match args {
+        [first, second] => Ok((first, second)),
+        _ => Err(wrong_arg_count(name, 2, args.len())),
+    }
+}
+
+fn wrong_arg_count(name: &str, expected: usize, given: usize) -> String {
+    format!(
+        "wrong number of args for {}: want {} got {}",
+        name, expected, given
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call_with(name: &str, arg: &str) -> String {
+        call(name, &[Value::from(arg)]).unwrap().to_string()
+    }
+
+    #[test]
+    fn quote_escapes_like_go() {
+        assert_eq!(call_with("quote", "plain"), r#""plain""#);
+        assert_eq!(call_with("quote", r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(call_with("quote", r"C:\dir"), r#""C:\\dir""#);
+        assert_eq!(call_with("quote", "a\nb\tc\r"), r#""a\nb\tc\r""#);
+        assert_eq!(call_with("quote", "\u{7}\u{1b}\u{7f}"), r#""\a\x1b\x7f""#);
+        assert_eq!(call_with("quote", "\u{85}"), r#""\u0085""#);
+        // printable unicode is kept as is
+        assert_eq!(
+            call_with("quote", "caf\u{e9} \u{2014}"),
+            "\"caf\u{e9} \u{2014}\""
+        );
+        assert_eq!(
+            call("quote", &[Value::from("a"), Value::Null, Value::Int(1)])
+                .unwrap()
+                .to_string(),
+            r#""a" "1""#
+        );
+    }
+
+    #[test]
+    fn camelcase_matches_sprig() {
+        // sprig's camelcase upper cases the first word as well
+        assert_eq!(call_with("camelcase", "http_server"), "HttpServer");
+        assert_eq!(call_with("camel", "feature-new-login"), "FeatureNewLogin");
+        assert_eq!(call_with("camel", "feature/x"), "FeatureX");
+    }
+
+    #[test]
+    fn case_conversions() {
+        assert_eq!(call_with("kebab", "Feature_NewLogin"), "feature-new-login");
+        assert_eq!(call_with("snake", "feature-newLogin"), "feature_new_login");
+        assert_eq!(call_with("title", "hello wide  world"), "Hello Wide  World");
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/**
+A node in the template variable tree. The root is always a `Map`; `{{ .Release.Name }}` walks
+`Release` then `Name` from the root.
+**/
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    pub(crate) fn new_map() -> Value {
+        Value::Map(BTreeMap::new())
+    }
+
+    /*
+    set a value at a dotted path like "Release.Name", creating intermediate maps as needed
+    */
+    pub(crate) fn set_path(&mut self, path: &str, value: Value) {
+        let segments: Vec<&str> = path.split('.').filter(|s| !s.is_empty()).collect();
+        self.set_segments(&segments, value);
+    }
+
+    fn set_segments(&mut self, segments: &[&str], value: Value) {
+        let (first, rest) = match segments.split_first() {
+            Some(split) => split,
+            None => {
+                *self = value;
+                return;
+            }
+        };
+        if let Value::Map(map) = self {
+            let child = map.entry((*first).to_string()).or_insert(Value::Null);
+            child.set_segments(rest, value);
+        } else {
+            *self = Value::new_map();
+            self.set_segments(segments, value);
+        }
+    }
+
+    /*
+    take the value at a dotted path out of the tree, so it reads as not set
+    */
+    pub(crate) fn remove_path(&mut self, path: &str) {
+        let segments: Vec<&str> = path.split('.').filter(|s| !s.is_empty()).collect();
+        if let Some((last, parents)) = segments.split_last() {
+            let mut current = self;
+            for segment in parents {
+                current = match current {
+                    Value::Map(map) => match map.get_mut(*segment) {
+                        Some(child) => child,
+                        None => return,
+                    },
+                    _ => return,
+                };
+            }
+            if let Value::Map(map) = current {
+                map.remove(*last);
+            }
+        }
+    }
+
+    /*
+    the value at a dotted path, replaced by an empty map when it doesn't exist yet
+    */
+    pub(crate) fn path_mut(&mut self, path: &str) -> &mut Value {
+        let mut current = self;
+        for segment in path.split('.').filter(|s| !s.is_empty()) {
+            if !matches!(current, Value::Map(_)) {
+                *current = Value::new_map();
+            }
+            current = match current {
+                Value::Map(map) => map.entry(segment.to_string()).or_insert(Value::Null),
+                _ => unreachable!(),
+            };
+        }
+        if !matches!(current, Value::Map(_)) {
+            *current = Value::new_map();
+        }
+        current
+    }
+
+    /*
+    deep merge `other` over this value: maps are merged key by key, anything else is replaced
+    */
+    pub(crate) fn merge(&mut self, other: Value) {
+        match (self, other) {
+            (Value::Map(map), Value::Map(other)) => {
+                for (key, value) in other {
+                    match map.get_mut(&key) {
+                        Some(existing) => existing.merge(value),
+                        None => {
+                            map.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (current, other) => *current = other,
+        }
+    }
+
+    /*
+    look up a path; map keys match case-insensitively like the original regex patterns did,
+    an exact match always wins
+    */
+    pub(crate) fn lookup<S: AsRef<str>>(&self, path: &[S]) -> Option<&Value> {
+        let mut current = self;
+        for segment in path {
+            current = current.child(segment.as_ref())?;
+        }
+        Some(current)
+    }
+
+    pub(crate) fn get_path(&self, path: &str) -> Option<&Value> {
+        let segments: Vec<&str> = path.split('.').filter(|s| !s.is_empty()).collect();
+        self.lookup(&segments)
+    }
+
+    fn child(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(map) => map.get(key).or_else(|| {
+                map.iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                    .map(|(_, v)| v)
+            }),
+            // {{ .Values.servers.0.port }} indexes into a list set with servers[0].port
+            Value::List(list) => key.parse::<usize>().ok().and_then(|index| list.get(index)),
+            _ => None,
+        }
+    }
+
+    /*
+    every leaf path in the tree, e.g. ".Release.Name"
+    */
+    pub(crate) fn leaf_paths(&self) -> Vec<String> {
+        let mut paths = Vec::new();
+        self.collect_paths("", &mut paths);
+        paths
+    }
+
+    fn collect_paths(&self, prefix: &str, paths: &mut Vec<String>) {
+        match self {
+            Value::Map(map) if !map.is_empty() => {
+                for (key, value) in map {
+                    value.collect_paths(&format!("{}.{}", prefix, key), paths);
+                }
+            }
+            Value::List(list) if !list.is_empty() => {
+                for (index, value) in list.iter().enumerate() {
+                    value.collect_paths(&format!("{}.{}", prefix, index), paths);
+                }
+            }
+            // an empty map such as .Vars with no vars files holds nothing to list
+            Value::Map(_) => {}
+            _ if !prefix.is_empty() => paths.push(prefix.to_string()),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        match self {
+            Value::Null => true,
+            Value::Bool(b) => !b,
+            Value::Int(i) => *i == 0,
+            Value::Float(f) => *f == 0.0,
+            Value::String(s) => s.is_empty(),
+            Value::List(list) => list.is_empty(),
+            Value::Map(map) => map.is_empty(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::String(s) => write!(f, "{}", s),
+            Value::List(list) => {
+                write!(f, "[")?;
+                for (i, value) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(value: serde_json::Value) -> Value {
+        match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(b),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => Value::Int(i),
+                None => Value::Float(n.as_f64().unwrap_or_default()),
+            },
+            serde_json::Value::String(s) => Value::String(s),
+            serde_json::Value::Array(list) => {
+                Value::List(list.into_iter().map(Value::from).collect())
+            }
+            serde_json::Value::Object(map) => Value::Map(
+                map.into_iter()
+                    .map(|(key, value)| (key, Value::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<&Value> for serde_json::Value {
+    fn from(value: &Value) -> serde_json::Value {
+        match value {
+            Value::Null => serde_json::Value::Null,
+            Value::Bool(b) => serde_json::Value::Bool(*b),
+            Value::Int(i) => serde_json::Value::from(*i),
+            // NaN and infinity have no JSON form and become null
+            Value::Float(f) => serde_json::Value::from(*f),
+            Value::String(s) => serde_json::Value::String(s.clone()),
+            Value::List(list) => {
+                serde_json::Value::Array(list.iter().map(serde_json::Value::from).collect())
+            }
+            Value::Map(map) => serde_json::Value::Object(
+                map.iter()
+                    .map(|(key, value)| (key.clone(), serde_json::Value::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<serde_yaml::Value> for Value {
+    fn from(value: serde_yaml::Value) -> Value {
+        match value {
+            serde_yaml::Value::Null => Value::Null,
+            serde_yaml::Value::Bool(b) => Value::Bool(b),
+            serde_yaml::Value::Number(n) => match n.as_i64() {
+                Some(i) => Value::Int(i),
+                None => Value::Float(n.as_f64().unwrap_or_default()),
+            },
+            serde_yaml::Value::String(s) => Value::String(s),
+            serde_yaml::Value::Sequence(list) => {
+                Value::List(list.into_iter().map(Value::from).collect())
+            }
+            serde_yaml::Value::Mapping(map) => Value::Map(
+                map.into_iter()
+                    .map(|(key, value)| (yaml_key(key), Value::from(value)))
+                    .collect(),
+            ),
+            serde_yaml::Value::Tagged(tagged) => Value::from(tagged.value),
+        }
+    }
+}
+
+/*
+YAML allows any scalar as a map key, the variable tree only has string keys
+*/
+fn yaml_key(key: serde_yaml::Value) -> String {
+    match key {
+        serde_yaml::Value::String(s) => s,
+        other => Value::from(other).to_string(),
+    }
+}
@@ -1,7 +1,11 @@
-use std::process::{Command as ProcessCommand, Stdio};
+use std::process::Command as ProcessCommand;
 
 use crate::command::Command;
+use crate::deploycheck;
+use crate::foilerror::FoilError;
+use crate::helmclient::HelmClient;
 use crate::helmruntime::HelmRuntime;
+use crate::templatevalue::Value;
 use clap::ArgMatches;
 
 pub(crate) struct UpgradeCommand<'a> {
@@ -9,7 +13,7 @@ pub(crate) struct UpgradeCommand<'a> {
 }
 
 impl<'a> UpgradeCommand<'a> {
-    pub(crate) fn new(execute_helm_command: &'a mut HelmRuntime) -> UpgradeCommand {
+    pub(crate) fn new(execute_helm_command: &'a mut HelmRuntime) -> UpgradeCommand<'a> {
         UpgradeCommand {
             helm_runtime: execute_helm_command,
         }
@@ -21,36 +25,164 @@ impl<'a> Command for UpgradeCommand<'a> {
         self.helm_runtime
     }
 
-    fn execute(&mut self, matches: &ArgMatches, commandline: &Option<&str>, helm_home_dir: String) {
+    fn execute(
+        &mut self,
+        matches: &ArgMatches,
+        commandline: &Option<&str>,
+        helm_binary: String,
+    ) -> Result<(), FoilError> {
         if let Some(command) = commandline {
             if let Some(upgrade_command) = matches.subcommand_matches(command) {
-                let mut helm_command = ProcessCommand::new(format!("{}/helm", helm_home_dir));
-                helm_command
-                    .stderr(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .arg(command);
-
-                if let Some(release) = upgrade_command.value_of("RELEASE") {
-                    helm_command.arg(release);
-                    // add global variable key/value 'release.name'
-                    self.get_helm_runtime()
-                        .set_implicit_var("release.name".to_string(), release.to_string());
-                }
-                self.get_helm_runtime()
-                    .get_and_set_chart_name(upgrade_command, &mut helm_command);
-
-                if upgrade_command.is_present("force") {
-                    helm_command.arg("--force");
-                }
+                let release = self
+                    .get_helm_runtime()
+                    .release_name(upgrade_command.value_of("RELEASE"))?
+                    .unwrap_or_default();
+                let client = HelmClient::new(&helm_binary, self.get_helm_runtime(), matches);
+                // the revision to go back to, taken before helm changes anything
+                let rollback_to = if upgrade_command.is_present("rollback-on-failure") {
+                    client.last_deployed(&release)?
+                } else {
+                    None
+                };
 
-                self.get_helm_runtime().apply_common_args(
+                let mut helm_command = prepare_upgrade(
+                    self.get_helm_runtime(),
                     matches,
                     upgrade_command,
-                    &mut helm_command,
-                );
+                    &helm_binary,
+                )?;
+                let result = self
+                    .get_helm_runtime()
+                    .execute_helm(&mut helm_command)
+                    .and_then(|_| match upgrade_command.value_of("check") {
+                        Some(check) => deploycheck::run_check(
+                            check,
+                            &[("HELM_FOIL_RELEASE_NAME", release.clone())],
+                        ),
+                        None => Ok(()),
+                    });
+
+                if let Err(error) = &result {
+                    if upgrade_command.is_present("rollback-on-failure") {
+                        eprintln!("upgrade of {} failed: {}", release, error);
+                        match rollback_to {
+                            Some(revision) => match client.rollback(&release, revision) {
+                                Ok(_) => {
+                                    eprintln!("rolled {} back to revision {}", release, revision)
+                                }
+                                Err(e) => eprintln!(
+                                    "rolling {} back to revision {} failed too: {}",
+                                    release, revision, e
+                                ),
+                            },
+                            None => eprintln!(
+                                "{} had no working revision before the upgrade, nothing to roll back to",
+                                release
+                            ),
+                        }
+                    }
+                }
+                return result;
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+One `helm upgrade RELEASE CHART` as the upgrade command and the workflows run it. The defaults
+are the upgrade command's: -f and --set from `values` and `set`, no `--install`.
+**/
+pub(crate) struct Upgrade<'a> {
+    pub(crate) release: Option<&'a str>,
+    pub(crate) chart: Option<&'a str>,
+    // the arguments on the subcommand holding the -f files and the --set values
+    pub(crate) value_files_arg: &'a str,
+    pub(crate) set_arg: &'a str,
+    pub(crate) install: bool,
+    // helm flags of the command's own, ahead of the common ones
+    pub(crate) flags: &'a [&'a str],
+    // set once the chart is loaded, so they win over what it and git say; `None` unsets
+    pub(crate) vars: &'a [(&'a str, Option<Value>)],
+}
 
-                self.get_helm_runtime().execute_helm(&mut helm_command)
+impl<'a> Upgrade<'a> {
+    pub(crate) fn new(release: Option<&'a str>, chart: Option<&'a str>) -> Upgrade<'a> {
+        Upgrade {
+            release,
+            chart,
+            value_files_arg: "valueFiles",
+            set_arg: "set",
+            install: false,
+            flags: &[],
+            vars: &[],
+        }
+    }
+
+    /*
+    the helm command with the values rendered
+    */
+    pub(crate) fn prepare(
+        &self,
+        helm_runtime: &mut HelmRuntime,
+        matches: &ArgMatches,
+        subcommand: &ArgMatches,
+        helm_binary: &str,
+    ) -> Result<ProcessCommand, FoilError> {
+        let mut helm_command = ProcessCommand::new(helm_binary);
+        helm_command.arg("upgrade");
+
+        if let Some(release) = self.release {
+            helm_command.arg(release);
+            // add global variable key/value 'Release.Name'
+            helm_runtime.set_var("Release.Name", Value::from(release));
+        }
+        let namespace = helm_runtime.release_namespace();
+        helm_runtime.set_var("Release.Namespace", Value::from(namespace));
+        if let Some(chart) = self.chart {
+            helm_runtime.load_chart(chart)?;
+        }
+        helm_command.arg(helm_runtime.chart_dir()?);
+        if self.install {
+            helm_command.arg("--install");
+        }
+        helm_command.args(self.flags);
+
+        for (path, value) in self.vars {
+            match value {
+                Some(value) => helm_runtime.set_var(path, value.clone()),
+                None => helm_runtime.unset_var(path),
             }
         }
+        helm_runtime.apply_common_args_from(
+            matches,
+            subcommand,
+            self.value_files_arg,
+            self.set_arg,
+            &mut helm_command,
+        )?;
+        Ok(helm_command)
+    }
+}
+
+/*
+`helm upgrade RELEASE CHART` for the upgrade command's arguments, also used by canary and deploy
+*/
+pub(crate) fn prepare_upgrade(
+    helm_runtime: &mut HelmRuntime,
+    matches: &ArgMatches,
+    upgrade_command: &ArgMatches,
+    helm_binary: &str,
+) -> Result<ProcessCommand, FoilError> {
+    let release = helm_runtime.release_name(upgrade_command.value_of("RELEASE"))?;
+    let flags: &[&str] = if upgrade_command.is_present("force") {
+        &["--force"]
+    } else {
+        &[]
+    };
+    Upgrade {
+        flags,
+        ..Upgrade::new(release.as_deref(), upgrade_command.value_of("CHART"))
     }
+    .prepare(helm_runtime, matches, upgrade_command, helm_binary)
 }
@@ -0,0 +1,154 @@
+use std::fs;
+use std::path::Path;
+
+use crate::foilerror::FoilError;
+use crate::templatevalue::Value;
+
+/**
+Variables that only feed the templates, read from `--vars-file` and `--var`. Nothing here is
+passed to helm, so template-only settings don't leak into the chart's values.
+
+The format is picked from the file name: `.json` is JSON, `.env` (or a name starting with
+`.env`) is dotenv, anything else is YAML.
+**/
+pub(crate) fn load(filename: &str) -> Result<Value, FoilError> {
+    let contents = fs::read_to_string(filename).map_err(|e| FoilError::io(filename, e))?;
+    let parse_error = |message: String| {
+        FoilError::Config(format!("error in vars file {}: {}", filename, message))
+    };
+
+    let path = Path::new(filename);
+    let basename = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+
+    let vars = if extension == "json" {
+        serde_json::from_str::<serde_json::Value>(&contents)
+            .map(Value::from)
+            .map_err(|e| parse_error(e.to_string()))?
+    } else if extension == "env" || basename.starts_with(".env") {
+        parse_dotenv(&contents).map_err(parse_error)?
+    } else if contents.trim().is_empty() {
+        Value::new_map()
+    } else {
+        serde_yaml::from_str::<serde_yaml::Value>(&contents)
+            .map(Value::from)
+            .map_err(|e| parse_error(e.to_string()))?
+    };
+
+    match vars {
+        Value::Map(_) => Ok(vars),
+        _ => Err(parse_error("expected a map of variables".to_string())),
+    }
+}
+
+/*
+KEY=value lines; blank lines, # comments and a leading `export` are skipped, and matching
+quotes around the value are removed. Dotted keys nest: db.host=x is {{ .Vars.db.host }}
+*/
+fn parse_dotenv(contents: &str) -> Result<Value, String> {
+    let mut vars = Value::new_map();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected KEY=value", number + 1))?;
+
+        let value = value.trim();
+        let unquoted = ['"', '\'']
+            .iter()
+            .find(|quote| {
+                value.len() >= 2 && value.starts_with(**quote) && value.ends_with(**quote)
+            })
+            .map(|_| &value[1..value.len() - 1])
+            .unwrap_or(value);
+        vars.set_path(key.trim(), Value::from(unquoted));
+    }
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_from(name: &str, contents: &str) -> Result<Value, FoilError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        load(path.to_str().unwrap())
+    }
+
+    #[test]
+    fn picks_the_format_from_the_name() {
+        let yaml = load_from("vars.yaml", "db:\n  host: x\n  port: 5432\n").unwrap();
+        assert_eq!(yaml.get_path("db.host"), Some(&Value::from("x")));
+        assert_eq!(yaml.get_path("db.port"), Some(&Value::Int(5432)));
+
+        let json = load_from("vars.json", r#"{"db": {"host": "y"}}"#).unwrap();
+        assert_eq!(json.get_path("db.host"), Some(&Value::from("y")));
+
+        for name in &["vars.env", ".env", ".env.local"] {
+            let dotenv = load_from(name, "db.host='z'\nTAG=\"1.0\"\n").unwrap();
+            assert_eq!(
+                dotenv.get_path("db.host"),
+                Some(&Value::from("z")),
+                "{}",
+                name
+            );
+            assert_eq!(
+                dotenv.get_path("TAG"),
+                Some(&Value::from("1.0")),
+                "{}",
+                name
+            );
+        }
+
+        assert_eq!(load_from("empty.yaml", "\n").unwrap(), Value::new_map());
+    }
+
+    #[test]
+    fn dotenv_values_are_strings_as_written() {
+        let vars = load_from(
+            "vars.env",
+            "# settings\n\nexport PORT=8080\nQUOTED=\"a # b\"\nEMPTY=\nURL=http://x?a=b\n",
+        )
+        .unwrap();
+        assert_eq!(vars.get_path("PORT"), Some(&Value::from("8080")));
+        assert_eq!(vars.get_path("QUOTED"), Some(&Value::from("a # b")));
+        assert_eq!(vars.get_path("EMPTY"), Some(&Value::from("")));
+        assert_eq!(vars.get_path("URL"), Some(&Value::from("http://x?a=b")));
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        for (name, contents) in &[
+            ("vars.yaml", "a: [1\n"),
+            ("vars.yaml", "- a\n- b\n"),
+            ("vars.yaml", "just a string\n"),
+            ("vars.json", "{\"a\": }"),
+            ("vars.json", "[1, 2]"),
+            ("vars.env", "A=1\nnot a pair\n"),
+        ] {
+            match load_from(name, contents) {
+                Err(FoilError::Config(message)) => {
+                    assert!(message.contains("error in vars file"), "{}", message)
+                }
+                other => panic!("{} {:?}: {:?}", name, contents, other),
+            }
+        }
+        match load_from("vars.env", "A=1\nnot a pair\n") {
+            Err(error) => assert!(error.to_string().ends_with("line 2: expected KEY=value")),
+            Ok(vars) => panic!("{:?}", vars),
+        }
+        assert!(matches!(
+            load("/nonexistent/vars.yaml"),
+            Err(FoilError::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,359 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use tar::Archive;
+use tempfile::{Builder, TempDir};
+
+use crate::foilerror::FoilError;
+use crate::helmruntime::STDIN_FILENAME;
+
+/**
+Private scratch directory that holds a copy of the chart, or the unpacked chart archive, and the
+rendered override files.
+helm is pointed at these copies so the source chart and -f files are never written to.
+The directory is removed when the workspace is dropped.
+**/
+#[derive(Debug)]
+pub(crate) struct Workspace {
+    dir: TempDir,
+    chart_dir: PathBuf,
+}
+
+// every gzip stream starts with these two bytes
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/*
+true for a packaged chart, e.g. mychart-1.2.0.tgz, whatever the file is called
+*/
+pub(crate) fn is_chart_archive(path: &Path) -> bool {
+    let mut magic = [0u8; 2];
+    path.is_file()
+        && fs::File::open(path)
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .is_ok()
+        && magic == GZIP_MAGIC
+}
+
+impl Workspace {
+    pub(crate) fn new(chart_path: &Path) -> Result<Workspace, FoilError> {
+        let dir = scratch_dir()?;
+
+        // keep the chart's directory name so helm reports the same chart path, taken from the
+        // canonical path so `.` or `../web/..` are named after the directory they point at
+        let chart_name = fs::canonicalize(chart_path)
+            .map_err(|e| FoilError::io(chart_path, e))?
+            .file_name()
+            .map(|name| name.to_os_string())
+            .unwrap_or_else(|| "chart".into());
+        let chart_dir = dir.path().join(chart_name);
+
+        copy_dir(chart_path, &chart_dir).map_err(|e| FoilError::io(chart_path, e))?;
+
+        Ok(Workspace { dir, chart_dir })
+    }
+
+    /*
+    unpack a packaged chart into the workspace; helm is given the unpacked directory
+    */
+    pub(crate) fn from_archive(archive_path: &Path) -> Result<Workspace, FoilError> {
+        let dir = scratch_dir()?;
+        unpack(archive_path, dir.path())?;
+
+        // helm packages a chart as a single top level directory named after it
+        let chart_dir = fs::read_dir(dir.path())
+            .map_err(|e| FoilError::io(dir.path(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .find(|path| path.join("Chart.yaml").is_file())
+            .ok_or_else(|| {
+                FoilError::Config(format!(
+                    "{} does not contain a chart directory with a Chart.yaml",
+                    archive_path.display()
+                ))
+            })?;
+
+        Ok(Workspace { dir, chart_dir })
+    }
+
+    pub(crate) fn chart_dir(&self) -> &Path {
+        &self.chart_dir
+    }
+
+    /*
+    every subchart under charts/, depth first; packaged subcharts are unpacked in place of their
+    archive so their values.yaml can be rendered
+    */
+    pub(crate) fn subchart_dirs(&self) -> Result<Vec<PathBuf>, FoilError> {
+        let mut subcharts = Vec::new();
+        collect_subcharts(&self.chart_dir, &mut subcharts)?;
+        Ok(subcharts)
+    }
+
+    /*
+    location of the rendered copy of an override file, prefixed with its position on the
+    command line so two files with the same name don't collide
+    */
+    pub(crate) fn override_path(&self, index: usize, override_filename: &str) -> PathBuf {
+        let filename = match override_filename {
+            STDIN_FILENAME => "stdin.yaml",
+            _ => Path::new(override_filename)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("values.yaml"),
+        };
+
+        self.dir
+            .path()
+            .join("overrides")
+            .join(format!("{}-{}", index, filename))
+    }
+
+    /*
+    a path inside the workspace relative to the workspace root
+    */
+    pub(crate) fn relative_path<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(self.dir.path()).unwrap_or(path)
+    }
+
+    pub(crate) fn write_file(&self, path: &Path, contents: &str) -> Result<(), FoilError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| FoilError::io(parent, e))?;
+        }
+        fs::write(path, contents).map_err(|e| FoilError::io(path, e))
+    }
+}
+
+fn collect_subcharts(chart_dir: &Path, subcharts: &mut Vec<PathBuf>) -> Result<(), FoilError> {
+    let charts_dir = chart_dir.join("charts");
+    if !charts_dir.is_dir() {
+        return Ok(());
+    }
+
+    for archive in sorted_entries(&charts_dir)? {
+        if is_chart_archive(&archive) {
+            unpack(&archive, &charts_dir)?;
+            // helm would load both the archive and the directory, keep only the directory
+            fs::remove_file(&archive).map_err(|e| FoilError::io(&archive, e))?;
+        }
+    }
+
+    for subchart in sorted_entries(&charts_dir)? {
+        if subchart.join("Chart.yaml").is_file() {
+            subcharts.push(subchart.clone());
+            collect_subcharts(&subchart, subcharts)?;
+        }
+    }
+    Ok(())
+}
+
+fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>, FoilError> {
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<PathBuf>>>()
+        })
+        .map_err(|e| FoilError::io(dir, e))?;
+    entries.sort();
+    Ok(entries)
+}
+
+fn unpack(archive_path: &Path, into: &Path) -> Result<(), FoilError> {
+    let archive = fs::File::open(archive_path).map_err(|e| FoilError::io(archive_path, e))?;
+    // unpack skips entries that would land outside the target directory
+    Archive::new(GzDecoder::new(archive))
+        .unpack(into)
+        .map_err(|e| FoilError::io(archive_path, e))
+}
+
+fn scratch_dir() -> Result<TempDir, FoilError> {
+    Builder::new()
+        .prefix("helm_foil")
+        .tempdir()
+        .map_err(|e| FoilError::io(env::temp_dir(), e))
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn write_chart(dir: &Path, name: &str) -> PathBuf {
+        let chart = dir.join(name);
+        fs::create_dir_all(chart.join("templates")).unwrap();
+        fs::write(chart.join("Chart.yaml"), format!("name: {}\n", name)).unwrap();
+        fs::write(chart.join("values.yaml"), "a: 1\n").unwrap();
+        fs::write(chart.join("templates/cm.yaml"), "kind: ConfigMap\n").unwrap();
+        chart
+    }
+
+    // (path in the archive, contents), paths are written as given so `..` gets through
+    fn archive(path: &Path, entries: &[(&str, &str)]) {
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            fs::File::create(path).unwrap(),
+            Compression::default(),
+        ));
+        for (name, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, contents.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn copies_the_chart_and_leaves_the_source_alone() {
+        let source = tempfile::tempdir().unwrap();
+        let chart = write_chart(source.path(), "web");
+        let workspace = Workspace::new(&chart).unwrap();
+
+        assert!(workspace.chart_dir().ends_with("web"));
+        assert_ne!(workspace.chart_dir(), chart);
+        assert_eq!(
+            fs::read_to_string(workspace.chart_dir().join("templates/cm.yaml")).unwrap(),
+            "kind: ConfigMap\n"
+        );
+        workspace
+            .write_file(&workspace.chart_dir().join("values.yaml"), "a: 2\n")
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(chart.join("values.yaml")).unwrap(),
+            "a: 1\n"
+        );
+    }
+
+    #[test]
+    fn names_the_copy_after_the_directory_a_relative_path_points_at() {
+        let source = tempfile::tempdir().unwrap();
+        let chart = write_chart(source.path(), "web");
+        fs::create_dir(chart.join("sub")).unwrap();
+
+        let workspace = Workspace::new(&chart.join("sub/..")).unwrap();
+        assert!(workspace.chart_dir().ends_with("web"));
+        assert!(workspace.chart_dir().join("Chart.yaml").is_file());
+    }
+
+    #[test]
+    fn follows_symlinks_when_copying() {
+        let source = tempfile::tempdir().unwrap();
+        let chart = write_chart(source.path(), "web");
+        fs::write(source.path().join("shared.yaml"), "shared: true\n").unwrap();
+        std::os::unix::fs::symlink(source.path().join("shared.yaml"), chart.join("shared.yaml"))
+            .unwrap();
+        std::os::unix::fs::symlink(chart.join("templates"), chart.join("linked")).unwrap();
+
+        let workspace = Workspace::new(&chart).unwrap();
+        let copied = workspace.chart_dir().join("shared.yaml");
+        assert!(!fs::symlink_metadata(&copied)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(copied).unwrap(), "shared: true\n");
+        assert!(workspace.chart_dir().join("linked/cm.yaml").is_file());
+    }
+
+    #[test]
+    fn detects_archives_by_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let packaged = dir.path().join("web-1.0.0.bin");
+        archive(&packaged, &[("web/Chart.yaml", "name: web\n")]);
+        let plain = dir.path().join("web.tgz");
+        fs::write(&plain, "name: web\n").unwrap();
+
+        assert!(is_chart_archive(&packaged));
+        assert!(!is_chart_archive(&plain));
+        assert!(!is_chart_archive(dir.path()));
+        assert!(!is_chart_archive(&dir.path().join("missing.tgz")));
+    }
+
+    #[test]
+    fn unpacks_an_archive_into_its_chart_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let packaged = dir.path().join("web-1.0.0.tgz");
+        archive(
+            &packaged,
+            &[
+                ("web/Chart.yaml", "name: web\n"),
+                ("web/values.yaml", "a: 1\n"),
+            ],
+        );
+
+        let workspace = Workspace::from_archive(&packaged).unwrap();
+        assert!(workspace.chart_dir().ends_with("web"));
+        assert!(workspace.chart_dir().join("values.yaml").is_file());
+
+        let empty = dir.path().join("empty.tgz");
+        archive(&empty, &[("README", "no chart here\n")]);
+        assert!(matches!(
+            Workspace::from_archive(&empty),
+            Err(FoilError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn unpacks_packaged_subcharts_in_place() {
+        let source = tempfile::tempdir().unwrap();
+        let chart = write_chart(source.path(), "web");
+        write_chart(&chart.join("charts"), "cache");
+        archive(
+            &chart.join("charts/db-1.0.0.tgz"),
+            &[
+                ("db/Chart.yaml", "name: db\n"),
+                ("db/charts/metrics/Chart.yaml", "name: metrics\n"),
+            ],
+        );
+
+        let workspace = Workspace::new(&chart).unwrap();
+        let charts = workspace.chart_dir().join("charts");
+        assert_eq!(
+            workspace.subchart_dirs().unwrap(),
+            vec![
+                charts.join("cache"),
+                charts.join("db"),
+                charts.join("db/charts/metrics"),
+            ]
+        );
+        assert!(!charts.join("db-1.0.0.tgz").exists());
+        // the source keeps its archive
+        assert!(chart.join("charts/db-1.0.0.tgz").is_file());
+    }
+
+    #[test]
+    fn refuses_to_unpack_outside_the_workspace() {
+        let dir = tempfile::tempdir().unwrap();
+        let packaged = dir.path().join("evil.tgz");
+        archive(
+            &packaged,
+            &[
+                ("web/Chart.yaml", "name: web\n"),
+                ("../escaped.yaml", "oops\n"),
+            ],
+        );
+
+        let workspace = Workspace::from_archive(&packaged).unwrap();
+        let outside = workspace.dir.path().parent().unwrap().join("escaped.yaml");
+        assert!(!outside.exists());
+        assert!(!dir.path().join("escaped.yaml").exists());
+    }
+}
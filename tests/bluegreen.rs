@@ -0,0 +1,125 @@
+//! `bluegreen` against a fake helm that keeps its releases, deploying branch after branch and
+//! switching the router between them.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::process::Output;
+
+use common::{FakeHelm, HELM3};
+
+const STATE: &str = ".helm_foil/bluegreen-web.json";
+
+fn bluegreen() -> FakeHelm {
+    let helm = FakeHelm::with_releases(HELM3);
+    helm.write("router/Chart.yaml", "name: router\nversion: 0.1.0\n");
+    helm.write("router/values.yaml", "live: {{ .Live.Release }}\n");
+    helm
+}
+
+/*
+deploy `branch` the way CI would, with the branch in GITHUB_HEAD_REF
+*/
+fn deploy(helm: &FakeHelm, branch: &str, extra: &[&str]) -> Output {
+    let mut args = vec!["bluegreen", "web", "chart", "--router-chart", "router"];
+    args.extend_from_slice(extra);
+    helm.command(&args)
+        .env("GITHUB_HEAD_REF", branch)
+        .output()
+        .unwrap()
+}
+
+fn state(helm: &FakeHelm) -> serde_json::Value {
+    serde_json::from_str(&fs::read_to_string(helm.path(STATE)).unwrap()).unwrap()
+}
+
+#[test]
+fn the_first_colour_has_no_previous_one() {
+    let helm = bluegreen();
+    assert!(deploy(&helm, "blue", &[]).status.success());
+
+    let calls = helm.calls();
+    assert!(calls[0].starts_with("upgrade web-blue "));
+    assert!(calls[0].contains(" --install"));
+    assert!(calls[1].starts_with("upgrade web "));
+    assert!(calls[1].contains("/router --install"));
+
+    let state = state(&helm);
+    assert_eq!(state["live_branch"], "blue");
+    assert_eq!(state["live_release"], "web-blue");
+    assert_eq!(state["router_release"], "web");
+    assert!(state["previous_branch"].is_null());
+    assert!(state["previous_release"].is_null());
+    assert!(helm.context("web").get("Previous").is_none());
+}
+
+#[test]
+fn the_router_is_switched_to_the_new_colour() {
+    let helm = bluegreen();
+    assert!(deploy(&helm, "blue", &[]).status.success());
+    assert!(deploy(&helm, "green", &[]).status.success());
+
+    let state = state(&helm);
+    assert_eq!(state["live_branch"], "green");
+    assert_eq!(state["live_release"], "web-green");
+    assert_eq!(state["previous_branch"], "blue");
+    assert_eq!(state["previous_release"], "web-blue");
+
+    let router = helm.context("web");
+    assert_eq!(router["Live"]["Release"], "web-green");
+    assert_eq!(router["Previous"]["Branch"], "blue");
+    assert_eq!(router["Previous"]["Release"], "web-blue");
+    // both colours are still there until --delete-old
+    assert_eq!(helm.revisions("web-blue"), ["1 deployed"]);
+    assert_eq!(helm.revisions("web-green"), ["1 deployed"]);
+}
+
+#[test]
+fn deploying_the_old_branch_again_switches_back() {
+    let helm = bluegreen();
+    assert!(deploy(&helm, "blue", &[]).status.success());
+    assert!(deploy(&helm, "green", &[]).status.success());
+    assert!(deploy(&helm, "blue", &[]).status.success());
+
+    let switched_back = state(&helm);
+    assert_eq!(switched_back["live_release"], "web-blue");
+    assert_eq!(switched_back["previous_release"], "web-green");
+    assert_eq!(helm.context("web")["Live"]["Release"], "web-blue");
+
+    // deploying the live branch again keeps the colour it replaced
+    assert!(deploy(&helm, "blue", &[]).status.success());
+    assert_eq!(state(&helm)["previous_release"], "web-green");
+}
+
+#[test]
+fn a_failed_switch_leaves_the_live_colour() {
+    let helm = bluegreen();
+    assert!(deploy(&helm, "blue", &[]).status.success());
+    helm.write("fail-upgrade-web", "");
+
+    let output = deploy(&helm, "green", &[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("web-blue is still live"));
+    let state = state(&helm);
+    assert_eq!(state["live_release"], "web-blue");
+    assert!(state["previous_release"].is_null());
+}
+
+#[test]
+fn delete_old_removes_the_replaced_colour() {
+    let helm = bluegreen();
+    assert!(deploy(&helm, "blue", &["--delete-old"]).status.success());
+    assert!(
+        deploy(&helm, "green", &["--delete-old", "--grace-period", "0"])
+            .status
+            .success()
+    );
+
+    assert_eq!(helm.calls().last().unwrap(), "uninstall web-blue");
+    assert!(helm.revisions("web-blue").is_empty());
+    let state = state(&helm);
+    assert_eq!(state["live_release"], "web-green");
+    assert_eq!(state["previous_branch"], "blue");
+    assert!(state["previous_release"].is_null());
+}
@@ -0,0 +1,157 @@
+//! `canary` against a fake helm that keeps the history of its releases, so rollouts can be
+//! interrupted, resumed and rolled back.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+
+use common::{FakeHelm, HELM3};
+
+const STATE: &str = ".helm_foil/canary-web.json";
+
+fn canary<'a>(extra: &[&'a str]) -> Vec<&'a str> {
+    let mut args = vec![
+        "canary",
+        "web",
+        "chart",
+        "--steps",
+        "25,50,100",
+        "--interval",
+        "0",
+    ];
+    args.extend_from_slice(extra);
+    args
+}
+
+fn state(helm: &FakeHelm) -> serde_json::Value {
+    serde_json::from_str(&fs::read_to_string(helm.path(STATE)).unwrap()).unwrap()
+}
+
+fn upgrades(helm: &FakeHelm) -> usize {
+    helm.calls()
+        .iter()
+        .filter(|call| call.starts_with("upgrade "))
+        .count()
+}
+
+#[test]
+fn a_rollout_records_every_step() {
+    let helm = FakeHelm::with_releases(HELM3);
+    helm.write("releases/web", "1 deployed\n");
+    let args = canary(&[]);
+    helm.stdout(&args);
+
+    assert_eq!(upgrades(&helm), 3);
+    let state = state(&helm);
+    assert_eq!(state["status"], "complete");
+    assert_eq!(state["steps"], serde_json::json!([25, 50, 100]));
+    assert_eq!(state["start_revision"], 1);
+    assert_eq!(
+        state["completed"],
+        serde_json::json!([
+            { "percentage": 25, "revision": 2 },
+            { "percentage": 50, "revision": 3 },
+            { "percentage": 100, "revision": 4 },
+        ])
+    );
+}
+
+#[test]
+fn a_failed_check_rolls_back_to_the_last_good_step() {
+    let helm = FakeHelm::with_releases(HELM3);
+    helm.write("releases/web", "1 deployed\n");
+    let args = canary(&["--check", "test \"$HELM_FOIL_CANARY_PERCENTAGE\" != 50"]);
+    let output = helm.output(&args);
+
+    assert!(!output.status.success());
+    assert_eq!(upgrades(&helm), 2);
+    // revision 2 is the 25% step, 3 the 50% step that failed its check
+    assert_eq!(helm.calls().last().unwrap(), "rollback web 2");
+    assert_eq!(state(&helm)["status"], "rolled-back");
+}
+
+#[test]
+fn a_failed_first_step_rolls_back_past_failed_revisions() {
+    let helm = FakeHelm::with_releases(HELM3);
+    // the newest revision is a failed one, the rollout has to go back to the one before it
+    helm.write("releases/web", "1 superseded\n2 deployed\n3 failed\n");
+    helm.write("fail-upgrade", "");
+    let args = canary(&[]);
+    let output = helm.output(&args);
+
+    assert!(!output.status.success());
+    assert_eq!(state(&helm)["start_revision"], 2);
+    assert_eq!(helm.calls().last().unwrap(), "rollback web 2");
+}
+
+#[test]
+fn resume_carries_on_after_the_completed_steps() {
+    let helm = FakeHelm::with_releases(HELM3);
+    helm.write("releases/web", "1 superseded\n2 deployed\n");
+    helm.write(
+        STATE,
+        r#"{
+            "release": "web",
+            "steps": [25, 50, 100],
+            "start_revision": 1,
+            "completed": [{ "percentage": 25, "revision": 2 }],
+            "status": "in-progress"
+        }"#,
+    );
+
+    // an interrupted rollout is left alone without --resume, and --resume needs the same steps
+    let args = canary(&[]);
+    let output = helm.output(&args);
+    assert_eq!(output.status.code(), Some(78));
+    let output = helm.output(&[
+        "canary",
+        "web",
+        "chart",
+        "--steps",
+        "10,100",
+        "--interval",
+        "0",
+        "--resume",
+    ]);
+    assert_eq!(output.status.code(), Some(78));
+    assert_eq!(upgrades(&helm), 0);
+
+    let args = canary(&["--resume"]);
+    helm.stdout(&args);
+    assert_eq!(upgrades(&helm), 2);
+    let state = state(&helm);
+    assert_eq!(state["status"], "complete");
+    assert_eq!(state["start_revision"], 1);
+    assert_eq!(
+        state["completed"],
+        serde_json::json!([
+            { "percentage": 25, "revision": 2 },
+            { "percentage": 50, "revision": 3 },
+            { "percentage": 100, "revision": 4 },
+        ])
+    );
+}
+
+#[test]
+fn a_resumed_rollout_rolls_back_to_its_last_good_step() {
+    let helm = FakeHelm::with_releases(HELM3);
+    helm.write("releases/web", "1 superseded\n2 deployed\n");
+    helm.write(
+        STATE,
+        r#"{
+            "release": "web",
+            "steps": [25, 50, 100],
+            "start_revision": 1,
+            "completed": [{ "percentage": 25, "revision": 2 }],
+            "status": "in-progress"
+        }"#,
+    );
+    helm.write("fail-upgrade", "");
+    let args = canary(&["--resume"]);
+    let output = helm.output(&args);
+
+    assert!(!output.status.success());
+    assert_eq!(helm.calls().last().unwrap(), "rollback web 2");
+    assert_eq!(state(&helm)["status"], "rolled-back");
+}
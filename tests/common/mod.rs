@@ -0,0 +1,220 @@
+//! A fake helm for running foil end to end: a shell script that answers `version --short` as
+//! Helm 2 or Helm 3 and records the command line it is given, next to a small chart.
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use tempfile::TempDir;
+
+pub const HELM2: &str = "Client: v2.16.1+gbbdfe5e";
+pub const HELM3: &str = "v3.5.0+g32c2223";
+
+const RELEASES_SCRIPT: &str = r#"d="$(dirname "$0")"
+printf '%s\n' "$*" >> "$d/calls"
+mkdir -p "$d/releases"
+case "$1" in
+  install) release=$2; prev=; for arg in "$@"; do [ "$prev" = --name ] && release=$arg; prev=$arg; done ;;
+  delete) release=$2; [ "$2" = --purge ] && release=$3 ;;
+  *) release=$2 ;;
+esac
+file="$d/releases/$release"
+next=$(( $(cat "$file" 2>/dev/null | wc -l) + 1 ))
+not_found() { echo "Error: release: not found" >&2; exit 1; }
+case "$1" in
+  history)
+    [ -s "$file" ] || not_found
+    awk 'BEGIN { printf "[" } { printf "%s{\"revision\":%s,\"status\":\"%s\"}", (NR > 1 ? "," : ""), $1, $2 } END { print "]" }' "$file" ;;
+  install|upgrade)
+    if [ -e "$d/fail-$1" ] || [ -e "$d/fail-$1-$release" ]; then
+      echo "$next failed" >> "$file"
+      echo "Error: $1 failed" >&2
+      exit 1
+    fi
+    sed -i 's/ deployed$/ superseded/' "$file" 2>/dev/null
+    echo "$next deployed" >> "$file"
+    printf '%s' "$HELM_FOIL_CONTEXT" > "$d/context-$release" ;;
+  rollback)
+    [ -s "$file" ] || not_found
+    [ -e "$d/fail-rollback" ] && { echo "Error: rollback failed" >&2; exit 1; }
+    sed -i 's/ deployed$/ superseded/' "$file"
+    echo "$next deployed" >> "$file" ;;
+  delete|uninstall)
+    [ -e "$file" ] || not_found
+    rm "$file" ;;
+esac
+"#;
+
+pub struct FakeHelm {
+    pub dir: TempDir,
+    pub helm: PathBuf,
+}
+
+impl FakeHelm {
+    pub fn new(version: &str) -> FakeHelm {
+        FakeHelm::with_script(
+            version,
+            "for arg in \"$@\"; do printf '%s\\n' \"$arg\"; done > \"$(dirname \"$0\")/argv\"\n",
+        )
+    }
+
+    /*
+    a fake helm that keeps releases in `releases/NAME`, one `REVISION STATUS` line per
+    revision, and logs every call to `calls`; `fail-upgrade` (or another command) makes that
+    command fail, `fail-upgrade-NAME` only for one release, leaving a failed revision behind
+    when it is an install or upgrade. The template variables of the last install or upgrade of
+    a release are kept in `context-NAME`
+    */
+    pub fn with_releases(version: &str) -> FakeHelm {
+        FakeHelm::with_script(version, RELEASES_SCRIPT)
+    }
+
+    /*
+    a fake helm that runs `script` for everything but `version`
+    */
+    pub fn with_script(version: &str, script: &str) -> FakeHelm {
+        let dir = tempfile::tempdir().unwrap();
+        let helm = dir.path().join("helm");
+        fs::write(
+            &helm,
+            format!(
+                "#!/bin/sh\n\
+                 if [ \"$1\" = version ]; then echo '{}'; exit 0; fi\n\
+                 {}",
+                version, script
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&helm, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let chart = dir.path().join("chart");
+        fs::create_dir(&chart).unwrap();
+        fs::write(chart.join("Chart.yaml"), "name: chart\nversion: 0.1.0\n").unwrap();
+        fs::write(chart.join("values.yaml"), "name: {{ .Release.Name }}\n").unwrap();
+        FakeHelm { dir, helm }
+    }
+
+    /*
+    the revisions of a release as `REVISION STATUS` lines, empty when it doesn't exist
+    */
+    pub fn revisions(&self, release: &str) -> Vec<String> {
+        fs::read_to_string(self.path(&format!("releases/{}", release)))
+            .map(|revisions| revisions.lines().map(|line| line.to_string()).collect())
+            .unwrap_or_default()
+    }
+
+    /*
+    every call to helm but `version`, each as its arguments joined by spaces
+    */
+    pub fn calls(&self) -> Vec<String> {
+        fs::read_to_string(self.path("calls"))
+            .map(|calls| calls.lines().map(|line| line.to_string()).collect())
+            .unwrap_or_default()
+    }
+
+    /*
+    the template variables foil last installed or upgraded a release with
+    */
+    pub fn context(&self, release: &str) -> serde_json::Value {
+        let context = fs::read_to_string(self.path(&format!("context-{}", release))).unwrap();
+        serde_json::from_str(&context).unwrap()
+    }
+
+    pub fn path(&self, relative: &str) -> PathBuf {
+        self.dir.path().join(relative)
+    }
+
+    pub fn write(&self, relative: &str, contents: &str) {
+        let path = self.path(relative);
+        fs::create_dir_all(path.parent().unwrap_or_else(|| Path::new("."))).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    pub fn output(&self, args: &[&str]) -> Output {
+        self.output_in("", args)
+    }
+
+    /*
+    run foil from a directory under the fake helm's
+    */
+    pub fn output_in(&self, relative_dir: &str, args: &[&str]) -> Output {
+        self.command(args)
+            .current_dir(self.path(relative_dir))
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    }
+
+    /*
+    foil set up to run this fake helm in its directory, without the CI settings of the
+    environment the tests run in
+    */
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_helm_foil"));
+        command
+            .args(args)
+            .current_dir(self.dir.path())
+            .env("HELM_FOIL_HELM_BIN", &self.helm)
+            .env_remove("CI")
+            .env_remove("HELM_FOIL_STRICT")
+            .env_remove("HELM_FOIL_CONTEXT");
+        command
+    }
+
+    /*
+    run foil with `stdin` piped in and extra environment variables, the way helm runs a
+    post-renderer
+    */
+    pub fn output_with(&self, args: &[&str], stdin: &str, env: &[(&str, &str)]) -> Output {
+        let mut child = self
+            .command(args)
+            .envs(env.iter().cloned())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    /*
+    run foil, which has to succeed, and return what it printed to stdout
+    */
+    pub fn stdout(&self, args: &[&str]) -> String {
+        let output = self.output(args);
+        assert!(
+            output.status.success(),
+            "helm_foil {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /*
+    run foil and return what helm was called with, the scratch chart directory as CHART
+    */
+    pub fn run(&self, args: &[&str]) -> Vec<String> {
+        self.stdout(args);
+        fs::read_to_string(self.path("argv"))
+            .unwrap()
+            .lines()
+            .map(|arg| {
+                if arg.ends_with("/chart") {
+                    "CHART".to_string()
+                } else {
+                    arg.to_string()
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,93 @@
+//! `deploy` against a fake Helm 2 that keeps the history of its releases, for the install,
+//! upgrade and purge-and-install paths.
+#![cfg(unix)]
+
+mod common;
+
+use common::{FakeHelm, HELM2, HELM3};
+
+const HISTORY: &str = "history web --output json";
+
+#[test]
+fn a_new_release_is_installed() {
+    let helm = FakeHelm::with_releases(HELM2);
+    helm.stdout(&["deploy", "web", "chart"]);
+
+    let calls = helm.calls();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[0], HISTORY);
+    assert!(calls[1].starts_with("install /"));
+    assert!(calls[1].ends_with("/chart --name web"));
+    assert_eq!(helm.revisions("web"), ["1 deployed"]);
+}
+
+#[test]
+fn an_existing_release_is_upgraded() {
+    let helm = FakeHelm::with_releases(HELM2);
+    helm.write(
+        "releases/web",
+        "1 SUPERSEDED\n2 FAILED\n3 DEPLOYED\n4 FAILED\n",
+    );
+    helm.stdout(&["deploy", "web", "chart"]);
+
+    let calls = helm.calls();
+    assert_eq!(calls.len(), 2);
+    assert!(calls[1].starts_with("upgrade web /"));
+    assert!(calls[1].ends_with("/chart"));
+    assert_eq!(helm.revisions("web").last().unwrap(), "5 deployed");
+}
+
+#[test]
+fn a_stuck_release_needs_recover() {
+    let helm = FakeHelm::with_releases(HELM2);
+    for history in &["1 FAILED\n", "1 PENDING_INSTALL\n"] {
+        helm.write("releases/web", history);
+        let output = helm.output(&["deploy", "web", "chart"]);
+        assert_eq!(output.status.code(), Some(78));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("use --recover"));
+    }
+    assert!(helm.calls().iter().all(|call| call == HISTORY));
+}
+
+#[test]
+fn recover_purges_and_installs_again() {
+    let helm = FakeHelm::with_releases(HELM2);
+    helm.write("releases/web", "1 FAILED\n");
+    helm.stdout(&["deploy", "web", "chart", "--recover"]);
+
+    let calls = helm.calls();
+    assert_eq!(calls.len(), 3);
+    assert_eq!(calls[0], HISTORY);
+    assert_eq!(calls[1], "delete --purge web");
+    assert!(calls[2].starts_with("install /"));
+    assert!(calls[2].ends_with("/chart --name web"));
+    assert_eq!(helm.revisions("web"), ["1 deployed"]);
+}
+
+#[test]
+fn recover_renders_before_it_purges() {
+    let helm = FakeHelm::with_releases(HELM2);
+    helm.write("releases/web", "1 PENDING_INSTALL\n");
+    helm.write("chart/values.yaml", "image: {{ .Vars.image }}\n");
+
+    let output = helm.output(&["deploy", "web", "chart", "--recover", "--strict"]);
+    assert_eq!(output.status.code(), Some(66));
+    let output = helm.output(&["deploy", "web", "chart", "--recover", "-f", "missing.yaml"]);
+    assert_eq!(output.status.code(), Some(74));
+
+    // the release is left for a run that renders
+    assert!(helm.calls().iter().all(|call| call == HISTORY));
+    assert_eq!(helm.revisions("web"), ["1 PENDING_INSTALL"]);
+}
+
+#[test]
+fn helm3_always_upgrades_with_install() {
+    let helm = FakeHelm::with_releases(HELM3);
+    helm.write("releases/web", "1 failed\n");
+    helm.stdout(&["deploy", "web", "chart"]);
+
+    let calls = helm.calls();
+    assert_eq!(calls.len(), 2);
+    assert!(calls[1].starts_with("upgrade web /"));
+    assert!(calls[1].ends_with("/chart --install"));
+}
@@ -0,0 +1,104 @@
+//! how foil runs helm: its exit status, the environment it is given and its output.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::process::Stdio;
+
+use common::{FakeHelm, HELM3};
+
+#[test]
+fn exits_with_helms_status() {
+    let helm = FakeHelm::with_script(HELM3, "exit 3\n");
+    let output = helm.output(&["install", "web", "chart"]);
+    assert_eq!(output.status.code(), Some(3));
+
+    let output = helm.output(&["install", "web", "chart", "--prefix-output"]);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn a_signal_exits_with_128_plus_its_number() {
+    let helm = FakeHelm::with_script(HELM3, "kill -TERM $$\n");
+    let output = helm.output(&["install", "web", "chart"]);
+    assert_eq!(output.status.code(), Some(128 + 15));
+
+    let output = helm.output(&["install", "web", "chart", "--prefix-output"]);
+    assert_eq!(output.status.code(), Some(128 + 15));
+}
+
+#[test]
+fn helm_gets_the_context_and_strictness_for_the_post_renderer() {
+    let helm = FakeHelm::with_script(
+        HELM3,
+        "printf '%s' \"$HELM_FOIL_CONTEXT\" > \"$(dirname \"$0\")/context\"\n\
+         printf '%s' \"$HELM_FOIL_STRICT\" > \"$(dirname \"$0\")/strict\"\n",
+    );
+    helm.write("chart/values.yaml", "name: {{ .Release.Name }}\n");
+
+    helm.stdout(&["install", "web", "chart", "--var", "tier=front", "--strict"]);
+    let context: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(helm.path("context")).unwrap()).unwrap();
+    assert_eq!(context["Release"]["Name"], "web");
+    assert_eq!(context["Chart"]["Name"], "chart");
+    assert_eq!(context["Vars"]["tier"], "front");
+    assert_eq!(fs::read_to_string(helm.path("strict")).unwrap(), "true");
+
+    helm.stdout(&["install", "web", "chart", "--lenient"]);
+    assert_eq!(fs::read_to_string(helm.path("strict")).unwrap(), "false");
+}
+
+#[test]
+fn prefixes_helms_output() {
+    let helm = FakeHelm::with_script(
+        HELM3,
+        "echo 'NAME: web'\necho 'STATUS: deployed'\necho 'warning: x' >&2\n",
+    );
+    let output = helm.output(&["install", "web", "chart", "--prefix-output"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[helm] NAME: web\n[helm] STATUS: deployed\n"
+    );
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .ends_with("[helm] warning: x\n"));
+
+    let output = helm.output(&["install", "web", "chart"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "NAME: web\nSTATUS: deployed\n"
+    );
+}
+
+#[test]
+fn streams_helms_output_as_it_is_printed() {
+    // helm waits for a file that is only created once its first line has been read
+    let helm = FakeHelm::with_script(
+        HELM3,
+        "echo first\n\
+         while [ ! -e \"$(dirname \"$0\")/go\" ]; do sleep 0.05; done\n\
+         echo second\n",
+    );
+    for args in &[
+        &["install", "web", "chart", "--prefix-output"][..],
+        &["install", "web", "chart"][..],
+    ] {
+        let _ = fs::remove_file(helm.path("go"));
+        let mut foil = helm
+            .command(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut lines = BufReader::new(foil.stdout.take().unwrap()).lines();
+
+        assert!(lines.next().unwrap().unwrap().ends_with("first"));
+        assert!(foil.try_wait().unwrap().is_none());
+        fs::write(helm.path("go"), "").unwrap();
+        assert!(lines.next().unwrap().unwrap().ends_with("second"));
+        assert!(foil.wait().unwrap().success());
+    }
+}
@@ -0,0 +1,274 @@
+//! foil against a fake helm that answers `version --short` as Helm 2 or Helm 3 and records the
+//! command line it is given.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+
+use common::{FakeHelm, HELM2, HELM3};
+
+#[test]
+fn helm2_install_names_the_release_with_name() {
+    let helm = FakeHelm::new(HELM2);
+    assert_eq!(
+        helm.run(&["install", "chart", "--name", "web"]),
+        ["install", "CHART", "--name", "web"]
+    );
+}
+
+#[test]
+fn helm3_install_takes_the_release_positionally() {
+    let helm = FakeHelm::new(HELM3);
+    assert_eq!(
+        helm.run(&["install", "chart", "--name", "web"]),
+        ["install", "web", "CHART"]
+    );
+    assert_eq!(
+        helm.run(&["install", "chart"]),
+        ["install", "CHART", "--generate-name"]
+    );
+}
+
+#[test]
+fn helm2_keeps_tiller_flags_and_seconds() {
+    let helm = FakeHelm::new(HELM2);
+    assert_eq!(
+        helm.run(&[
+            "upgrade",
+            "web",
+            "chart",
+            "--tiller-namespace",
+            "kube-system",
+            "--timeout",
+            "300",
+            "--create-namespace",
+            "--tls",
+            "--tls-cert",
+            "cert.pem",
+        ]),
+        [
+            "upgrade",
+            "web",
+            "CHART",
+            "--tiller-namespace",
+            "kube-system",
+            "--timeout",
+            "300",
+            "--tls",
+            "--tls-cert",
+            "cert.pem",
+        ]
+    );
+}
+
+#[test]
+fn helm3_drops_tiller_flags_and_converts_the_timeout() {
+    let helm = FakeHelm::new(HELM3);
+    assert_eq!(
+        helm.run(&[
+            "upgrade",
+            "web",
+            "chart",
+            "--tiller-namespace",
+            "kube-system",
+            "--timeout",
+            "300",
+            "--create-namespace",
+            "--tls",
+            "--tls-cert",
+            "cert.pem",
+        ]),
+        [
+            "upgrade",
+            "web",
+            "CHART",
+            "--create-namespace",
+            "--timeout",
+            "300s"
+        ]
+    );
+}
+
+#[test]
+fn forwarded_flags_keep_their_place() {
+    let helm = FakeHelm::new(HELM3);
+    assert_eq!(
+        helm.run(&["upgrade", "--skip-schema-validation", "web", "chart"]),
+        ["upgrade", "--skip-schema-validation", "web", "CHART"]
+    );
+    assert_eq!(
+        helm.run(&["upgrade", "--version", "1.2.3", "web", "chart", "--wait"]),
+        ["upgrade", "--version", "1.2.3", "web", "CHART", "--wait"]
+    );
+}
+
+#[test]
+fn foil_flags_are_not_passed_through() {
+    let helm = FakeHelm::new(HELM3);
+    let helm_binary = helm.helm.to_str().unwrap().to_string();
+    let helm_binary_eq = format!("--helm-binary={}", helm_binary);
+    let foil_flags: &[&[&str]] = &[
+        &["--helm-binary", &helm_binary],
+        &[&helm_binary_eq],
+        &["--helm-version", "3"],
+        &["--prefix-output"],
+        &["--strict"],
+        &["--lenient"],
+        &["--vars-file", "vars.yaml"],
+        &["--vars-file=vars.yaml"],
+        &["--var", "a=b"],
+        &["--allow-env", "BUILD_*"],
+        &["--release-name-from-branch"],
+    ];
+    for flags in foil_flags {
+        let mut args = flags.to_vec();
+        args.extend(&["status", "web", "--namespace", "apps"]);
+        assert_eq!(
+            helm.run(&args),
+            ["status", "web", "--namespace", "apps"],
+            "{:?}",
+            flags
+        );
+    }
+}
+
+#[test]
+fn usage_errors_exit_64() {
+    let helm = FakeHelm::new(HELM3);
+    let output = helm.output(&["install"]);
+    assert_eq!(output.status.code(), Some(64));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("required arguments"), "{}", stderr);
+
+    let output = helm.output(&["install", "--output", "json"]);
+    assert_eq!(output.status.code(), Some(64));
+    let report: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(report["error"], "usage");
+    assert_eq!(report["exit_code"], 64);
+
+    let output = helm.output(&["--help"]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn foil_errors_exit_with_their_code() {
+    let helm = FakeHelm::new(HELM3);
+    let output = helm.output(&["install", "missing", "-o", "json"]);
+    assert_eq!(output.status.code(), Some(78));
+    let report: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(report["error"], "config");
+
+    fs::write(
+        helm.dir.path().join("chart/values.yaml"),
+        "a: {{ .Release.Name\n",
+    )
+    .unwrap();
+    let output = helm.output(&["install", "chart", "--name", "web", "-o", "json"]);
+    assert_eq!(output.status.code(), Some(65));
+    let report: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(report["line"], 1);
+
+    fs::write(
+        helm.dir.path().join("chart/values.yaml"),
+        "a: {{ .Vars.missing }}\n",
+    )
+    .unwrap();
+    let output = helm.output(&["install", "chart", "--strict"]);
+    assert_eq!(output.status.code(), Some(66));
+}
+
+#[test]
+fn helm_failures_exit_with_helms_code() {
+    let helm = FakeHelm::new(HELM3);
+    let output = helm.output(&["--helm-binary", "/bin/false", "status", "web"]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = helm.output(&["--helm-binary", "/nonexistent/helm", "status", "web"]);
+    assert_eq!(output.status.code(), Some(69));
+}
+
+#[test]
+fn install_takes_helm3_name_chart_and_namespace() {
+    let helm = FakeHelm::new(HELM3);
+    assert_eq!(
+        helm.run(&["install", "web", "chart", "-n", "apps"]),
+        ["install", "web", "CHART", "--namespace", "apps"]
+    );
+    assert_eq!(
+        helm.run(&["install", "chart", "-n", "apps", "--name", "web"]),
+        ["install", "web", "CHART", "--namespace", "apps"]
+    );
+    assert_eq!(
+        helm.run(&["install", "web", "chart", "--namespace", "apps"]),
+        ["install", "web", "CHART", "--namespace", "apps"]
+    );
+}
+
+#[test]
+fn install_under_helm2_reads_n_as_the_name() {
+    let helm = FakeHelm::new(HELM2);
+    assert_eq!(
+        helm.run(&["install", "chart", "-n", "web", "--namespace", "apps"]),
+        ["install", "CHART", "--name", "web", "--namespace", "apps"]
+    );
+    // the Helm 3 form is translated for Helm 2
+    assert_eq!(
+        helm.run(&["install", "web", "chart"]),
+        ["install", "CHART", "--name", "web"]
+    );
+}
+
+#[test]
+fn release_namespace_is_the_one_helm_deploys_to() {
+    let helm = FakeHelm::with_script(
+        HELM3,
+        "printf '%s' \"$HELM_FOIL_CONTEXT\" > \"$(dirname \"$0\")/context\"\n",
+    );
+    helm.write(
+        "kubeconfig",
+        "current-context: dev\n\
+         contexts:\n\
+         - name: dev\n  context:\n    namespace: team\n\
+         - name: prod\n  context:\n    namespace: live\n",
+    );
+    let kubeconfig = helm.path("kubeconfig");
+    let namespace = |args: &[&str], kubeconfig: &std::path::Path| {
+        let output = helm
+            .command(args)
+            .env("KUBECONFIG", kubeconfig)
+            .env_remove("HELM_NAMESPACE")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "helm_foil {:?} failed", args);
+        let context: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(helm.path("context")).unwrap()).unwrap();
+        context["Release"]["Namespace"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    };
+
+    assert_eq!(
+        namespace(&["install", "web", "chart", "-n", "apps"], &kubeconfig),
+        "apps"
+    );
+    assert_eq!(
+        namespace(
+            &["upgrade", "web", "chart", "--namespace", "apps"],
+            &kubeconfig
+        ),
+        "apps"
+    );
+    assert_eq!(namespace(&["install", "web", "chart"], &kubeconfig), "team");
+    assert_eq!(
+        namespace(
+            &["upgrade", "web", "chart", "--kube-context", "prod"],
+            &kubeconfig
+        ),
+        "live"
+    );
+    assert_eq!(
+        namespace(&["install", "web", "chart"], &helm.path("missing")),
+        "default"
+    );
+}
@@ -0,0 +1,112 @@
+//! `preview up` and `preview gc` against a fake helm that keeps its releases, with the preview
+//! registry in between.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::process::Output;
+
+use common::{FakeHelm, HELM3};
+
+const REGISTRY: &str = ".helm_foil/preview-registry.json";
+
+/*
+`preview up` for `branch` the way CI would run it, with the branch in GITHUB_HEAD_REF
+*/
+fn up(helm: &FakeHelm, branch: &str) -> Output {
+    helm.command(&["preview", "up", "chart"])
+        .env("GITHUB_HEAD_REF", branch)
+        .output()
+        .unwrap()
+}
+
+fn registered(helm: &FakeHelm) -> Vec<(String, String, String)> {
+    let registry: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(helm.path(REGISTRY)).unwrap()).unwrap();
+    registry["releases"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|preview| {
+            let field = |name: &str| preview[name].as_str().unwrap().to_string();
+            (field("release"), field("namespace"), field("branch"))
+        })
+        .collect()
+}
+
+fn preview(release: &str, branch: &str) -> (String, String, String) {
+    (release.to_string(), release.to_string(), branch.to_string())
+}
+
+#[test]
+fn up_deploys_the_branch_in_its_own_namespace() {
+    let helm = FakeHelm::with_releases(HELM3);
+    assert!(up(&helm, "feature/Login").status.success());
+
+    let calls = helm.calls();
+    assert_eq!(calls.len(), 1);
+    assert!(calls[0].starts_with("upgrade preview-feature-login "));
+    assert!(
+        calls[0].ends_with("/chart --install --namespace preview-feature-login --create-namespace")
+    );
+    let context = helm.context("preview-feature-login");
+    assert_eq!(context["Release"]["Name"], "preview-feature-login");
+    assert_eq!(context["Release"]["Namespace"], "preview-feature-login");
+    assert_eq!(context["Preview"]["Namespace"], "preview-feature-login");
+}
+
+#[test]
+fn up_records_every_preview_once() {
+    let helm = FakeHelm::with_releases(HELM3);
+    assert!(up(&helm, "one").status.success());
+    assert!(up(&helm, "two").status.success());
+    assert!(up(&helm, "one").status.success());
+
+    assert_eq!(
+        registered(&helm),
+        [preview("preview-two", "two"), preview("preview-one", "one")]
+    );
+    assert_eq!(
+        helm.revisions("preview-one"),
+        ["1 superseded", "2 deployed"]
+    );
+}
+
+#[test]
+fn gc_deletes_the_previews_of_gone_branches() {
+    let helm = FakeHelm::with_releases(HELM3);
+    assert!(up(&helm, "one").status.success());
+    assert!(up(&helm, "two").status.success());
+    helm.write("branches", "# still open\none\n");
+
+    // a dry run leaves everything as it is
+    helm.stdout(&["preview", "gc", "--keep-branches", "branches", "--dry-run"]);
+    assert_eq!(helm.calls().len(), 3);
+    assert_eq!(registered(&helm).len(), 2);
+
+    helm.stdout(&["preview", "gc", "--keep-branches", "branches"]);
+    assert_eq!(
+        helm.calls().last().unwrap(),
+        "uninstall preview-two --namespace preview-two"
+    );
+    assert!(helm.revisions("preview-two").is_empty());
+    assert_eq!(registered(&helm), [preview("preview-one", "one")]);
+}
+
+#[test]
+fn gc_drops_previews_that_were_deleted_by_hand() {
+    let helm = FakeHelm::with_releases(HELM3);
+    assert!(up(&helm, "one").status.success());
+    fs::remove_file(helm.path("releases/preview-one")).unwrap();
+    helm.write("branches", "");
+
+    let output = helm.output(&["preview", "gc", "--keep-branches", "branches"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already gone"));
+    assert!(!helm
+        .calls()
+        .iter()
+        .any(|call| call.starts_with("uninstall")));
+    assert!(registered(&helm).is_empty());
+}
@@ -0,0 +1,216 @@
+//! foil rendering values files without running helm.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+
+use common::{FakeHelm, HELM3};
+
+#[test]
+fn vars_come_from_files_and_var_flags_in_order() {
+    let helm = FakeHelm::new(HELM3);
+    helm.write(
+        "chart/values.yaml",
+        "a: {{ .Vars.a }}\nb: {{ .Vars.b }}\nc: {{ .Vars.db.host }}\nd: {{ .Vars.d }}\n",
+    );
+    helm.write("base.yaml", "a: base\nb: base\ndb:\n  host: base\n");
+    helm.write("override.json", r#"{"b": "json", "db": {"host": "json"}}"#);
+    helm.write("local.env", "export d=\"dotenv\"\n# comment\n");
+
+    let rendered = helm.stdout(&[
+        "render",
+        "chart",
+        "--vars-file",
+        "base.yaml",
+        "--vars-file",
+        "override.json",
+        "--vars-file",
+        "local.env",
+        "--var",
+        "db.host=cli",
+    ]);
+    assert!(
+        rendered.contains("a: base\nb: json\nc: cli\nd: dotenv\n"),
+        "{}",
+        rendered
+    );
+}
+
+#[test]
+fn malformed_vars_files_are_config_errors() {
+    let helm = FakeHelm::new(HELM3);
+    helm.write("broken.yaml", "a: [1\n");
+    helm.write("list.yaml", "- a\n- b\n");
+    helm.write("broken.env", "no equals sign\n");
+    for file in &["broken.yaml", "list.yaml", "broken.env"] {
+        let output = helm.output(&["render", "chart", "--vars-file", file]);
+        assert_eq!(output.status.code(), Some(78), "{}", file);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(&format!("error in vars file {}", file)),
+            "{}",
+            stderr
+        );
+    }
+
+    let output = helm.output(&["render", "chart", "--vars-file", "missing.yaml"]);
+    assert_eq!(output.status.code(), Some(74));
+    let output = helm.output(&["render", "chart", "--var", "novalue"]);
+    assert_eq!(output.status.code(), Some(78));
+}
+
+#[test]
+fn chart_name_without_chart_yaml_is_the_directory_name() {
+    let helm = FakeHelm::new(HELM3);
+    helm.write("web/values.yaml", "chart: {{ .Chart.Name }}\n");
+
+    let output = helm.output_in("web", &["render", "."]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let rendered = String::from_utf8(output.stdout).unwrap();
+    assert!(rendered.contains("chart: web\n"), "{}", rendered);
+
+    let rendered = helm.stdout(&["render", "web"]);
+    assert!(rendered.contains("chart: web\n"), "{}", rendered);
+}
+
+const MANIFEST: &str = "kind: ConfigMap\n\
+                        data:\n  release: {{ .Release.Name }}\n  \
+                        branch: {{ .Branch.Name }}\n  \
+                        alert: '{{ .Labels.instance }}'\n";
+
+#[test]
+fn bare_foil_with_piped_stdin_is_the_post_renderer() {
+    let helm = FakeHelm::new(HELM3);
+    // what foil hands helm, which passes it on to the post-renderer it starts
+    let context = r#"{"Release": {"Name": "web"}, "Branch": {"Name": "main"}}"#;
+
+    let output = helm.output_with(&[], MANIFEST, &[("HELM_FOIL_CONTEXT", context)]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "kind: ConfigMap\ndata:\n  release: web\n  branch: main\n  alert: '{{ .Labels.instance }}'\n"
+    );
+}
+
+#[test]
+fn post_render_takes_its_context_from_flags() {
+    let helm = FakeHelm::new(HELM3);
+    let output = helm.output_with(
+        &["post-render", "--name", "api", "--chart", "chart"],
+        "release: {{ .Release.Name }}\nchart: {{ .Chart.Name }}\n",
+        &[],
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "release: api\nchart: chart\n"
+    );
+}
+
+#[test]
+fn post_render_is_as_strict_as_the_foil_that_started_helm() {
+    let helm = FakeHelm::new(HELM3);
+    let context = r#"{"Release": {"Name": "web"}}"#;
+    let manifest = "release: {{ .Release.Name }}\nsha: {{ .Git.Sha }}\n";
+
+    let output = helm.output_with(
+        &[],
+        manifest,
+        &[("HELM_FOIL_CONTEXT", context), ("HELM_FOIL_STRICT", "true")],
+    );
+    assert_eq!(output.status.code(), Some(66));
+    assert!(output.stdout.is_empty());
+
+    let output = helm.output_with(
+        &[],
+        manifest,
+        &[
+            ("HELM_FOIL_CONTEXT", context),
+            ("HELM_FOIL_STRICT", "false"),
+        ],
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "release: web\nsha: {{ .Git.Sha }}\n"
+    );
+}
+
+#[test]
+fn render_prints_every_rendered_file_without_running_helm() {
+    let helm = FakeHelm::new(HELM3);
+    helm.write("chart/charts/db/Chart.yaml", "name: db\nversion: 1.0.0\n");
+    helm.write(
+        "chart/charts/db/values.yaml",
+        "host: {{ .Release.Name }}-{{ .Subchart.Name }}\n",
+    );
+    helm.write("prod.yaml", "replicas: {{ .Values.replicas }}");
+
+    let rendered = helm.stdout(&[
+        "render",
+        "chart",
+        "--name",
+        "web",
+        "-f",
+        "prod.yaml",
+        "--set",
+        "replicas=3",
+    ]);
+    assert_eq!(
+        rendered,
+        "---\n# Source: chart/values.yaml\nname: web\n\
+         ---\n# Source: chart/charts/db/values.yaml\nhost: web-db\n\
+         ---\n# Source: prod.yaml\nreplicas: 3\n"
+    );
+    assert!(!helm.path("argv").exists());
+}
+
+#[test]
+fn render_writes_to_the_output_dir_and_leaves_the_sources_alone() {
+    let helm = FakeHelm::new(HELM3);
+    helm.write("chart/charts/db/Chart.yaml", "name: db\nversion: 1.0.0\n");
+    helm.write(
+        "chart/charts/db/values.yaml",
+        "host: {{ .Release.Name }}-db\n",
+    );
+    helm.write("prod.yaml", "env: {{ .Release.Name }}\n");
+
+    let rendered = helm.stdout(&[
+        "render",
+        "chart",
+        "--name",
+        "web",
+        "-f",
+        "prod.yaml",
+        "--output-dir",
+        "out",
+    ]);
+    assert_eq!(rendered, "");
+    let read = |relative: &str| fs::read_to_string(helm.path(relative)).unwrap();
+    assert_eq!(read("out/chart/values.yaml"), "name: web\n");
+    assert_eq!(read("out/chart/charts/db/values.yaml"), "host: web-db\n");
+    assert_eq!(read("out/overrides/0-prod.yaml"), "env: web\n");
+    // only rendered values files are written, not the rest of the chart
+    assert!(!helm.path("out/chart/Chart.yaml").exists());
+
+    assert_eq!(read("chart/values.yaml"), "name: {{ .Release.Name }}\n");
+    assert_eq!(
+        read("chart/charts/db/values.yaml"),
+        "host: {{ .Release.Name }}-db\n"
+    );
+    assert_eq!(read("prod.yaml"), "env: {{ .Release.Name }}\n");
+    assert!(!helm.path("argv").exists());
+}
@@ -0,0 +1,85 @@
+//! `upgrade --rollback-on-failure` against a fake helm that keeps the history of its releases.
+#![cfg(unix)]
+
+mod common;
+
+use common::{FakeHelm, HELM2, HELM3};
+
+#[test]
+fn helm2_history_and_rollback_reach_the_same_tiller() {
+    let helm = FakeHelm::with_releases(HELM2);
+    helm.write("releases/web", "1 SUPERSEDED\n2 DEPLOYED\n");
+    helm.write("fail-upgrade", "");
+    let output = helm.output(&[
+        "upgrade",
+        "web",
+        "chart",
+        "--rollback-on-failure",
+        "--tiller-namespace",
+        "tiller",
+        "--tls",
+        "--tls-ca-cert",
+        "ca.pem",
+        "--tls-cert=cert.pem",
+        "--tls-key",
+        "key.pem",
+        "--tls-verify",
+        "--host",
+        "tiller.example:44134",
+        "--home",
+        "/helm",
+        "--kube-context",
+        "prod",
+        "--wait",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let connection = "--tiller-namespace tiller --tls --tls-ca-cert ca.pem --tls-cert=cert.pem \
+                      --tls-key key.pem --tls-verify --host tiller.example:44134 --home /helm \
+                      --kube-context prod";
+    let calls = helm.calls();
+    assert_eq!(calls.len(), 3);
+    assert_eq!(
+        calls[0],
+        format!("history web --output json {}", connection)
+    );
+    assert!(calls[1].starts_with("upgrade web /"));
+    assert!(calls[1].ends_with(" --wait"));
+    // the revision that failed is never the one to go back to
+    assert_eq!(calls[2], format!("rollback web 2 {}", connection));
+}
+
+#[test]
+fn helm3_rollback_stays_in_the_namespace_and_drops_tiller_flags() {
+    let helm = FakeHelm::with_releases(HELM3);
+    helm.write("releases/web", "1 deployed\n2 failed\n");
+    let output = helm.output(&[
+        "upgrade",
+        "web",
+        "chart",
+        "--rollback-on-failure",
+        "--namespace",
+        "apps",
+        "--tls",
+        "--kubeconfig",
+        "/kube/config",
+        "--check",
+        "false",
+    ]);
+    assert!(!output.status.success());
+
+    let calls = helm.calls();
+    assert_eq!(calls.len(), 3);
+    assert_eq!(
+        calls[0],
+        "history web --output json --namespace apps --kubeconfig /kube/config"
+    );
+    assert_eq!(
+        calls[2],
+        "rollback web 1 --namespace apps --kubeconfig /kube/config"
+    );
+    assert_eq!(
+        helm.revisions("web"),
+        ["1 superseded", "2 failed", "3 superseded", "4 deployed"]
+    );
+}